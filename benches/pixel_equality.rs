@@ -0,0 +1,71 @@
+//! Channel-by-channel pixel equality (3 `eq` + 2 `and`, five bootstraps per
+//! pixel) against the packed lookup table (one bootstrap per pixel).
+//!
+//! Run with `cargo bench --bench pixel_equality`.
+
+// Test modules of the included files are built without their #[test] items
+#![allow(dead_code, unused_imports)]
+
+// The crate is a binary, so the modules under test are compiled in directly
+#[path = "../src/color_chroma.rs"]
+mod color_chroma;
+#[path = "../src/color_hsv.rs"]
+mod color_hsv;
+#[path = "../src/count_rgb.rs"]
+mod count_rgb;
+#[path = "../src/encrypt_image.rs"]
+mod encrypt_image;
+#[path = "../src/mask.rs"]
+mod mask;
+#[path = "../src/morphology.rs"]
+mod morphology;
+#[path = "../src/packed_eq.rs"]
+mod packed_eq;
+
+use std::time::{Duration, Instant};
+
+use image::{DynamicImage, Rgb, RgbImage};
+use rand::Rng;
+
+use count_rgb::match_rgb_bits;
+use encrypt_image::{create_keys, encrypt_image_with_halo, merge_encrypted_blocks, quantize};
+use packed_eq::match_packed_bits;
+
+/// Packed mode fits three channels of this many bits in the 3 bit profile.
+const BITS: u32 = 1;
+const SIZE: u32 = 8;
+const ROUNDS: u32 = 3;
+
+fn time<T>(mut f: impl FnMut() -> T) -> Duration {
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        f();
+    }
+    start.elapsed() / ROUNDS
+}
+
+fn main() {
+    let (client_key, server_key) = create_keys(3 * BITS).unwrap();
+    let mut rng = rand::thread_rng();
+    let img = RgbImage::from_fn(SIZE, SIZE, |_, _| Rgb([rng.r#gen(), rng.r#gen(), rng.r#gen()]));
+    let img = DynamicImage::ImageRgb8(img);
+    let blocks = encrypt_image_with_halo(&img, SIZE, BITS, 0, &client_key);
+    let enc_img = merge_encrypted_blocks(&blocks, SIZE, SIZE, &client_key);
+    let ref_px = img.to_rgb8().get_pixel(0, 0).0;
+    let ref_rgb = ref_px.map(|c| client_key.encrypt(quantize(c, BITS)));
+
+    // Both paths must agree before their timings mean anything
+    let exact = match_rgb_bits(&enc_img, &ref_rgb, &server_key);
+    let packed = match_packed_bits(&enc_img, &ref_rgb, BITS, &server_key);
+    for (e, p) in exact.iter().zip(&packed) {
+        assert_eq!(client_key.decrypt(e), client_key.decrypt(p));
+    }
+
+    let pixels = SIZE * SIZE;
+    let exact = time(|| match_rgb_bits(&enc_img, &ref_rgb, &server_key));
+    let packed = time(|| match_packed_bits(&enc_img, &ref_rgb, BITS, &server_key));
+    println!("pixel equality over {} pixels, {} bit(s) per channel", pixels, BITS);
+    println!("  exact  {:>12?} ({:?}/pixel)", exact, exact / pixels);
+    println!("  packed {:>12?} ({:?}/pixel)", packed, packed / pixels);
+    println!("  speedup x{:.2}", exact.as_secs_f64() / packed.as_secs_f64());
+}
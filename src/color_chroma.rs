@@ -0,0 +1,77 @@
+use rayon::prelude::*;
+use tfhe::shortint::server_key::BivariateLookupTableOwned;
+use tfhe::shortint::{Ciphertext, ServerKey};
+
+use crate::encrypt_image::EncryptedImage;
+
+/// Tolerance used when matching normalized chromaticity.
+#[derive(Clone, Copy, Debug)]
+pub struct ChromaTolerance {
+    /// Maximum difference of r/(r+g+b) and g/(r+g+b), as a fraction.
+    pub tolerance: f32,
+}
+
+impl Default for ChromaTolerance {
+    fn default() -> Self {
+        ChromaTolerance { tolerance: 0.05 }
+    }
+}
+
+/// Lookup tables for chromaticity matching.
+/// Ratios are quantized to `0..modulus`; black pixels map to the neutral
+/// value 1/3 since their chromaticity is undefined.
+pub struct ChromaLuts {
+    half_sum: BivariateLookupTableOwned,
+    ratio: BivariateLookupTableOwned,
+    close: BivariateLookupTableOwned,
+}
+
+impl ChromaLuts {
+    pub fn new(tol: &ChromaTolerance, server_key: &ServerKey) -> Self {
+        let m = server_key.message_modulus.0;
+        let steps = (tol.tolerance * (m - 1) as f32).round() as u64;
+        ChromaLuts {
+            // (b + c) / 2 keeps the sum of the other two channels in range
+            half_sum: server_key.generate_lookup_table_bivariate(|b, c| (b + c) / 2),
+            ratio: server_key.generate_lookup_table_bivariate(move |a, half| {
+                let sum = a + 2 * half;
+                (a * (m - 1)).checked_div(sum).unwrap_or((m - 1) / 3)
+            }),
+            close: server_key.generate_lookup_table_bivariate(move |a, b| (a.abs_diff(b) <= steps) as u64),
+        }
+    }
+}
+
+/// Encrypted chromaticity (r/(r+g+b), g/(r+g+b)) of one pixel.
+pub fn chromaticity(px: &[Ciphertext], luts: &ChromaLuts, server_key: &ServerKey) -> [Ciphertext; 2] {
+    let lut2 = |a: &Ciphertext, b: &Ciphertext, lut: &BivariateLookupTableOwned| {
+        server_key.apply_lookup_table_bivariate(a, b, lut)
+    };
+    let (r, g, b) = (&px[0], &px[1], &px[2]);
+    let cr = lut2(r, &lut2(g, b, &luts.half_sum), &luts.ratio);
+    let cg = lut2(g, &lut2(r, b, &luts.half_sum), &luts.ratio);
+    [cr, cg]
+}
+
+/// Homomorphic chromaticity match of every pixel against the reference.
+/// Scaling all three channels together leaves the ratios unchanged, so
+/// shadows and highlights on the same object still match.
+pub fn match_chroma_bits(
+    enc_img: &EncryptedImage,
+    ref_rgb: &[Ciphertext; 3],
+    tol: &ChromaTolerance,
+    server_key: &ServerKey,
+) -> Vec<Ciphertext> {
+    let luts = ChromaLuts::new(tol, server_key);
+    let [ref_r, ref_g] = chromaticity(ref_rgb, &luts, server_key);
+    enc_img
+        .data
+        .par_chunks(3)
+        .map(|px| {
+            let [cr, cg] = chromaticity(px, &luts, server_key);
+            let r_ok = server_key.apply_lookup_table_bivariate(&cr, &ref_r, &luts.close);
+            let g_ok = server_key.apply_lookup_table_bivariate(&cg, &ref_g, &luts.close);
+            server_key.and(&r_ok, &g_ok)
+        })
+        .collect()
+}
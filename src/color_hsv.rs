@@ -0,0 +1,138 @@
+use rayon::prelude::*;
+use tfhe::shortint::server_key::{BivariateLookupTableOwned, LookupTableOwned};
+use tfhe::shortint::{Ciphertext, ServerKey};
+
+use crate::encrypt_image::EncryptedImage;
+
+/// Tolerances used when matching pixels in HSV space.
+/// Hue is compared as a circular window, saturation and value only need to
+/// reach their floors so that shaded parts of an object still count.
+#[derive(Clone, Copy, Debug)]
+pub struct HsvTolerance {
+    /// Maximum circular hue distance in degrees.
+    pub hue_window: u32,
+    /// Minimum saturation as a fraction in `0.0..=1.0`.
+    pub min_saturation: f32,
+    /// Minimum value as a fraction in `0.0..=1.0`.
+    pub min_value: f32,
+}
+
+impl Default for HsvTolerance {
+    fn default() -> Self {
+        HsvTolerance {
+            hue_window: 20,
+            min_saturation: 0.2,
+            min_value: 0.2,
+        }
+    }
+}
+
+/// Lookup tables evaluated by programmable bootstrapping.
+/// Every channel value lives in `0..modulus`; hue is quantized into
+/// `modulus` buckets around the colour wheel.
+pub struct HsvLuts {
+    max: BivariateLookupTableOwned,
+    min: BivariateLookupTableOwned,
+    ge: BivariateLookupTableOwned,
+    gt: BivariateLookupTableOwned,
+    half_diff: BivariateLookupTableOwned,
+    hue_sector: [BivariateLookupTableOwned; 3],
+    bit_mul: BivariateLookupTableOwned,
+    saturation: BivariateLookupTableOwned,
+    hue_close: BivariateLookupTableOwned,
+    sat_floor: LookupTableOwned,
+    val_floor: LookupTableOwned,
+}
+
+impl HsvLuts {
+    pub fn new(tol: &HsvTolerance, server_key: &ServerKey) -> Self {
+        let m = server_key.message_modulus.0;
+        // (a - b) is stored halved and offset by m / 2 so that it stays in range
+        let decode_diff = move |x: u64| 2 * x as i64 - m as i64;
+        let hue_bucket = move |deg: i64| ((deg.rem_euclid(360) as u64) * m / 360) % m;
+        let sector = |base: i64| {
+            server_key.generate_lookup_table_bivariate(move |diff, delta| {
+                if delta == 0 {
+                    return 0;
+                }
+                hue_bucket(base + 60 * decode_diff(diff) / delta as i64)
+            })
+        };
+        let window = (tol.hue_window as u64 * m).div_ceil(360);
+        let sat_floor = (tol.min_saturation * (m - 1) as f32).round() as u64;
+        let val_floor = (tol.min_value * (m - 1) as f32).round() as u64;
+
+        HsvLuts {
+            max: server_key.generate_lookup_table_bivariate(|a, b| a.max(b)),
+            min: server_key.generate_lookup_table_bivariate(|a, b| a.min(b)),
+            ge: server_key.generate_lookup_table_bivariate(|a, b| (a >= b) as u64),
+            gt: server_key.generate_lookup_table_bivariate(|a, b| (a > b) as u64),
+            half_diff: server_key.generate_lookup_table_bivariate(move |a, b| (a + m - b) / 2),
+            hue_sector: [sector(0), sector(120), sector(240)],
+            bit_mul: server_key.generate_lookup_table_bivariate(|bit, v| bit * v),
+            saturation: server_key.generate_lookup_table_bivariate(move |delta, v| {
+                (delta * (m - 1)).checked_div(v).unwrap_or(0)
+            }),
+            hue_close: server_key.generate_lookup_table_bivariate(move |h, r| {
+                let d = h.abs_diff(r);
+                (d.min(m - d) <= window) as u64
+            }),
+            sat_floor: server_key.generate_lookup_table(move |s| (s >= sat_floor) as u64),
+            val_floor: server_key.generate_lookup_table(move |v| (v >= val_floor) as u64),
+        }
+    }
+}
+
+/// Convert one encrypted RGB pixel to encrypted (hue, saturation, value).
+pub fn rgb_to_hsv(px: &[Ciphertext], luts: &HsvLuts, server_key: &ServerKey) -> [Ciphertext; 3] {
+    let lut2 = |a: &Ciphertext, b: &Ciphertext, lut: &BivariateLookupTableOwned| {
+        server_key.apply_lookup_table_bivariate(a, b, lut)
+    };
+    let (r, g, b) = (&px[0], &px[1], &px[2]);
+
+    // Value and chroma
+    let v = lut2(&lut2(r, g, &luts.max), b, &luts.max);
+    let min = lut2(&lut2(r, g, &luts.min), b, &luts.min);
+    let delta = server_key.sub(&v, &min);
+
+    // Which channel holds the maximum (ties resolved r, g, b)
+    let is_r = server_key.and(&lut2(r, g, &luts.ge), &lut2(r, b, &luts.ge));
+    let is_g = server_key.and(&lut2(g, r, &luts.gt), &lut2(g, b, &luts.ge));
+    let is_b = server_key.and(&lut2(b, r, &luts.gt), &lut2(b, g, &luts.gt));
+
+    // Hue candidate of each sector, selected by the sector bit
+    let h_r = lut2(&lut2(g, b, &luts.half_diff), &delta, &luts.hue_sector[0]);
+    let h_g = lut2(&lut2(b, r, &luts.half_diff), &delta, &luts.hue_sector[1]);
+    let h_b = lut2(&lut2(r, g, &luts.half_diff), &delta, &luts.hue_sector[2]);
+    let hue = server_key.add(
+        &server_key.add(&lut2(&is_r, &h_r, &luts.bit_mul), &lut2(&is_g, &h_g, &luts.bit_mul)),
+        &lut2(&is_b, &h_b, &luts.bit_mul),
+    );
+
+    let s = lut2(&delta, &v, &luts.saturation);
+    [hue, s, v]
+}
+
+/// Homomorphic HSV match of every pixel against the reference colour.
+/// A pixel matches when its hue lies within the window around the reference
+/// hue and its saturation and value reach the configured floors.
+pub fn match_hsv_bits(
+    enc_img: &EncryptedImage,
+    ref_rgb: &[Ciphertext; 3],
+    tol: &HsvTolerance,
+    server_key: &ServerKey,
+) -> Vec<Ciphertext> {
+    let luts = HsvLuts::new(tol, server_key);
+    let [ref_hue, _, _] = rgb_to_hsv(ref_rgb, &luts, server_key);
+    enc_img
+        .data
+        .par_chunks(3)
+        .map(|px| {
+            let [h, s, v] = rgb_to_hsv(px, &luts, server_key);
+            let hue_ok = server_key.apply_lookup_table_bivariate(&h, &ref_hue, &luts.hue_close);
+            let s_ok = server_key.apply_lookup_table(&s, &luts.sat_floor);
+            let v_ok = server_key.apply_lookup_table(&v, &luts.val_floor);
+            server_key.and(&server_key.and(&hue_ok, &s_ok), &v_ok)
+        })
+        .collect()
+}
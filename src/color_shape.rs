@@ -0,0 +1,87 @@
+use std::collections::BTreeMap;
+
+use image::{GrayImage, Luma};
+use imageproc::contours::{find_contours, BorderType};
+use tfhe::shortint::ClientKey;
+
+use crate::count_rgb::component_labels;
+use crate::mask::{decrypt_mask, mask_to_bools, EncryptedMask};
+use crate::shape_class::{classify, ShapeClass, ShapeFeatures};
+
+/// Result of a combined colour-and-shape query.
+#[derive(Clone, Debug)]
+pub struct ColorShapeCounts {
+    /// Class of the selected object.
+    pub target: ShapeClass,
+    /// Components of the reference colour whose class is `target`.
+    pub matching: u32,
+    /// Components of the reference colour per class.
+    pub by_class: BTreeMap<ShapeClass, u32>,
+}
+
+/// Classify every connected component of a boolean map.
+/// Each component is drawn alone on a padded canvas of its bounding box so
+/// that touching components of other labels do not disturb its contour.
+pub fn component_classes(width: u32, height: u32, map: &[bool]) -> (Vec<u32>, Vec<ShapeClass>) {
+    let (labels, count) = component_labels(width, height, map);
+    let mut bbox = vec![(u32::MAX, u32::MAX, 0u32, 0u32); count as usize];
+    for (idx, &label) in labels.iter().enumerate() {
+        if label == 0 {
+            continue;
+        }
+        let (x, y) = (idx as u32 % width, idx as u32 / width);
+        let b = &mut bbox[label as usize - 1];
+        *b = (b.0.min(x), b.1.min(y), b.2.max(x), b.3.max(y));
+    }
+
+    let classes = bbox
+        .iter()
+        .enumerate()
+        .map(|(i, &(x0, y0, x1, y1))| {
+            let label = i as u32 + 1;
+            let mut canvas = GrayImage::new(x1 - x0 + 3, y1 - y0 + 3);
+            for y in y0..=y1 {
+                for x in x0..=x1 {
+                    if labels[(y * width + x) as usize] == label {
+                        canvas.put_pixel(x - x0 + 1, y - y0 + 1, Luma([255u8]));
+                    }
+                }
+            }
+            find_contours::<i32>(&canvas)
+                .into_iter()
+                .filter(|c| c.border_type == BorderType::Outer)
+                .max_by_key(|c| c.points.len())
+                .map_or(ShapeClass::Other, |c| classify(&ShapeFeatures::from_contour(&c.points)))
+        })
+        .collect();
+    (labels, classes)
+}
+
+/// Client side: decrypt the colour match mask, classify each matching
+/// component and count those with the same class as the component under
+/// `reference` (the selected pixel). Answers "how many red triangles".
+/// Returns `None` when the selected pixel is not part of any component.
+pub fn count_color_shape(
+    mask: &EncryptedMask,
+    reference: (u32, u32),
+    client_key: &ClientKey,
+) -> Option<ColorShapeCounts> {
+    let map = mask_to_bools(&decrypt_mask(mask, client_key));
+    let (labels, classes) = component_classes(mask.width, mask.height, &map);
+
+    let ref_label = labels[(reference.1 * mask.width + reference.0) as usize];
+    if ref_label == 0 {
+        return None;
+    }
+    let target = classes[ref_label as usize - 1];
+
+    let mut by_class = BTreeMap::new();
+    for class in &classes {
+        *by_class.entry(*class).or_insert(0) += 1;
+    }
+    Some(ColorShapeCounts {
+        target,
+        matching: by_class.get(&target).copied().unwrap_or(0),
+        by_class,
+    })
+}
@@ -1,20 +1,27 @@
+use crate::color_chroma::{match_chroma_bits, ChromaTolerance};
+use crate::color_hsv::{match_hsv_bits, HsvTolerance};
 use crate::encrypt_image::EncryptedImage;
+use crate::mask::{decrypt_mask, mask_to_bools, EncryptedMask};
+use crate::morphology::{apply_morphology, MorphOp};
+use crate::packed_eq::match_packed_bits;
 use tfhe::shortint::{Ciphertext, ClientKey, ServerKey};
 
-/// Internal: run connected component labeling on a boolean image.
-fn ccl(width: u32, height: u32, map: &[bool]) -> u32 {
-    let mut visited = vec![false; map.len()];
+/// Connected component labeling on a boolean image.
+/// Returns one label per pixel, 0 for background and 1.. for components in
+/// raster order of their first pixel, together with the number of components.
+pub fn component_labels(width: u32, height: u32, map: &[bool]) -> (Vec<u32>, u32) {
+    let mut labels = vec![0u32; map.len()];
     let mut count = 0u32;
     let dirs = [(1i32, 0i32), (-1, 0), (0, 1), (0, -1)];
     for y in 0..height {
         for x in 0..width {
             let idx = (y * width + x) as usize;
-            if map[idx] && !visited[idx] {
+            if map[idx] && labels[idx] == 0 {
                 count += 1;
                 let mut stack = vec![idx];
                 while let Some(cur) = stack.pop() {
-                    if visited[cur] { continue; }
-                    visited[cur] = true;
+                    if labels[cur] != 0 { continue; }
+                    labels[cur] = count;
                     let cx = (cur as u32) % width;
                     let cy = (cur as u32) / width;
                     for (dx, dy) in dirs.iter() {
@@ -22,7 +29,7 @@ fn ccl(width: u32, height: u32, map: &[bool]) -> u32 {
                         let ny = cy as i32 + dy;
                         if nx >= 0 && ny >= 0 && nx < width as i32 && ny < height as i32 {
                             let nidx = (ny as u32 * width + nx as u32) as usize;
-                            if map[nidx] && !visited[nidx] {
+                            if map[nidx] && labels[nidx] == 0 {
                                 stack.push(nidx);
                             }
                         }
@@ -31,20 +38,35 @@ fn ccl(width: u32, height: u32, map: &[bool]) -> u32 {
             }
         }
     }
-    count
+    (labels, count)
+}
+
+/// Internal: run connected component labeling on a boolean image.
+fn ccl(width: u32, height: u32, map: &[bool]) -> u32 {
+    component_labels(width, height, map).1
 }
 
-/// Count how many objects (connected components) in the encrypted image match
-/// the reference RGB value.
-/// RGB comparison is performed homomorphically and then a plain CCL step
-/// groups adjacent matching pixels.
-pub fn count_rgb_objects(
+/// How pixels are compared with the reference colour.
+#[derive(Clone, Copy, Debug)]
+pub enum ColorMode {
+    /// Exact RGB equality.
+    Exact,
+    /// Hue window plus saturation / value floors, robust to lighting.
+    Hsv(HsvTolerance),
+    /// Normalized r/(r+g+b), g/(r+g+b) within a tolerance, robust to brightness.
+    Chromaticity(ChromaTolerance),
+    /// Exact equality with the whole pixel packed into one lookup table;
+    /// the image must be encrypted with this many bits per channel.
+    Packed(u32),
+}
+
+/// Homomorphic equality check of every pixel against the reference RGB value.
+/// Returns one encrypted match bit per pixel in row major order.
+pub fn match_rgb_bits(
     enc_img: &EncryptedImage,
-    ref_rgb: [Ciphertext; 3],
-    client_key: &ClientKey,
+    ref_rgb: &[Ciphertext; 3],
     server_key: &ServerKey,
-) -> u32 {
-    // 1. equality check for each pixel in the encrypted domain
+) -> Vec<Ciphertext> {
     let mut eq_pixels = Vec::with_capacity((enc_img.width * enc_img.height) as usize);
     for px in enc_img.data.chunks(3) {
         let r = server_key.eq(&px[0], &ref_rgb[0]);
@@ -54,13 +76,70 @@ pub fn count_rgb_objects(
         let rgb = server_key.and(&rg, &b);
         eq_pixels.push(rgb);
     }
+    eq_pixels
+}
+
+/// Homomorphic match of every pixel against the reference colour in the
+/// given mode.
+pub fn match_color_bits(
+    enc_img: &EncryptedImage,
+    ref_rgb: &[Ciphertext; 3],
+    mode: &ColorMode,
+    server_key: &ServerKey,
+) -> Vec<Ciphertext> {
+    match mode {
+        ColorMode::Exact => match_rgb_bits(enc_img, ref_rgb, server_key),
+        ColorMode::Hsv(tol) => match_hsv_bits(enc_img, ref_rgb, tol, server_key),
+        ColorMode::Chromaticity(tol) => match_chroma_bits(enc_img, ref_rgb, tol, server_key),
+        ColorMode::Packed(bits) => match_packed_bits(enc_img, ref_rgb, *bits, server_key),
+    }
+}
+
+/// Server side: build the encrypted match mask for the reference colour,
+/// cleaned up by the optional morphological operations.
+/// Nothing is decrypted; releasing the mask is left to the client.
+pub fn rgb_match_mask(
+    enc_img: &EncryptedImage,
+    ref_rgb: &[Ciphertext; 3],
+    mode: &ColorMode,
+    morph: &[MorphOp],
+    server_key: &ServerKey,
+) -> EncryptedMask {
+    let bits = match_color_bits(enc_img, ref_rgb, mode, server_key);
+    let bits = apply_morphology(enc_img.width, enc_img.height, bits, morph, server_key);
+    EncryptedMask::new(enc_img.width, enc_img.height, bits)
+}
 
-    // 2. decrypt boolean map
-    let bool_map: Vec<bool> = eq_pixels
-        .iter()
-        .map(|c| client_key.decrypt(c) != 0)
-        .collect();
+/// Client side: decrypt an encrypted mask and count its connected components.
+pub fn count_mask_objects(mask: &EncryptedMask, client_key: &ClientKey) -> u32 {
+    // 1. decrypt boolean map
+    let bool_map = mask_to_bools(&decrypt_mask(mask, client_key));
 
-    // 3. connected component labeling on plaintext boolean map
-    ccl(enc_img.width, enc_img.height, &bool_map)
+    // 2. connected component labeling on plaintext boolean map
+    ccl(mask.width, mask.height, &bool_map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn labels_follow_raster_order_of_first_pixel() {
+        #[rustfmt::skip]
+        let map = [
+            false, true,  false, true,
+            false, true,  false, true,
+            true,  true,  false, false,
+        ];
+        let (labels, count) = component_labels(4, 3, &map);
+        assert_eq!(count, 2);
+        assert_eq!(labels, vec![0, 1, 0, 2, 0, 1, 0, 2, 1, 1, 0, 0]);
+    }
+
+    #[test]
+    fn diagonal_pixels_are_separate_components() {
+        let map = [true, false, false, true];
+        assert_eq!(component_labels(2, 2, &map).1, 2);
+        assert_eq!(component_labels(2, 2, &[false; 4]), (vec![0; 4], 0));
+    }
 }
@@ -1,49 +1,276 @@
+use std::collections::BTreeMap;
+
 use image::{DynamicImage, GrayImage};
-use imageproc::contours::{find_contours, BorderType, Contour};
-use crate::encrypt_image::EncryptedImage;
+use imageproc::contours::Contour;
+use imageproc::contrast::{adaptive_threshold, otsu_level, threshold};
+use crate::feature_match::{encrypt_features, encrypt_range, features_in_range, FeatureTolerance};
+use crate::hierarchy::ContourTree;
+use crate::fourier::{descriptor_distance, fourier_descriptor, ContourDescriptor, DEFAULT_HARMONICS};
+use crate::moments::{hu_distance, hu_moments, region_moments};
+use crate::shape_class::{classify, ShapeClass, ShapeFeatures};
+use crate::pixel_count::{counter_blocks, sum_bits, EncryptedCount};
 use tfhe::shortint::{Ciphertext, ClientKey, ServerKey};
 
-/// Detects polygons using contours and returns number of sides for the largest
-/// contour inside the given rectangle.
-fn detect_shape(img: &GrayImage, rect: (u32, u32, u32, u32)) -> usize {
+/// How the gray image is binarized before contour extraction.
+#[derive(Clone, Copy, Debug)]
+pub enum Binarization {
+    /// Feed the raw gray values to `find_contours`.
+    None,
+    /// Global threshold chosen by Otsu's method.
+    Otsu,
+    /// Compare each pixel with the mean of its `(2r + 1)^2` neighbourhood.
+    AdaptiveMean(u32),
+    /// Global threshold: pixels brighter than the value are foreground.
+    Fixed(u8),
+}
+
+/// Preprocessing applied before any contour search.
+#[derive(Clone, Copy, Debug)]
+pub struct Preprocess {
+    pub binarization: Binarization,
+    /// Swap foreground and background, for dark objects on a light backdrop.
+    pub invert: bool,
+}
+
+impl Default for Preprocess {
+    fn default() -> Self {
+        Preprocess {
+            binarization: Binarization::Otsu,
+            invert: false,
+        }
+    }
+}
+
+impl Binarization {
+    /// Parse `none`, `otsu`, `adaptive:<radius>` or `fixed:<level>`.
+    /// A bare `adaptive` uses radius 7.
+    pub fn parse(spec: &str) -> Option<Self> {
+        let (kind, arg) = spec.split_once(':').unwrap_or((spec, ""));
+        match kind {
+            "none" => Some(Binarization::None),
+            "otsu" => Some(Binarization::Otsu),
+            "adaptive" if arg.is_empty() => Some(Binarization::AdaptiveMean(7)),
+            "adaptive" => arg.parse().ok().map(Binarization::AdaptiveMean),
+            "fixed" => arg.parse().ok().map(Binarization::Fixed),
+            _ => None,
+        }
+    }
+}
+
+/// Convert the image to gray and binarize it as configured.
+pub fn binarize(img: &DynamicImage, prep: &Preprocess) -> GrayImage {
+    let gray = img.to_luma8();
+    let mut out = match prep.binarization {
+        Binarization::None => gray,
+        Binarization::Otsu => threshold(&gray, otsu_level(&gray)),
+        Binarization::AdaptiveMean(radius) => adaptive_threshold(&gray, radius.max(1)),
+        Binarization::Fixed(level) => threshold(&gray, level),
+    };
+    if prep.invert {
+        image::imageops::invert(&mut out);
+    }
+    out
+}
+
+/// Outer borders of the foreground regions, each with its number of holes.
+pub fn objects(img: &GrayImage) -> Vec<(Contour<i32>, usize)> {
+    ContourTree::new(img).into_objects()
+}
+
+/// Outer borders of the foreground regions; hole borders are skipped.
+fn outer_contours(img: &GrayImage) -> Vec<Contour<i32>> {
+    objects(img).into_iter().map(|(c, _)| c).collect()
+}
+
+/// Largest object inside the given rectangle, in ROI coordinates, with its
+/// number of holes.
+fn reference_object(img: &GrayImage, rect: (u32, u32, u32, u32)) -> Option<(Contour<i32>, usize)> {
     let (x, y, w, h) = rect;
     let sub_image = image::imageops::crop_imm(img, x, y, w, h).to_image();
-    let contours = find_contours::<u8>(&sub_image, BorderType::Outer);
-    contours
-        .iter()
-        .map(Contour::len)
-        .max()
-        .unwrap_or(0)
+    objects(&sub_image)
+        .into_iter()
+        .max_by_key(|(c, _)| c.points.len())
 }
 
-/// Count shapes with the same number of contour points as the reference shape.
-pub fn count_same_shape(img: &DynamicImage, rect: (u32, u32, u32, u32)) -> u32 {
+/// Euler number (one minus the number of holes) of the largest object
+/// inside the given rectangle, or `None` if it is empty.
+pub fn reference_euler_number(img: &DynamicImage, rect: (u32, u32, u32, u32), prep: &Preprocess) -> Option<i32> {
+    let (x, y, w, h) = rect;
+    let binary = binarize(img, prep);
+    let tree = ContourTree::new(&image::imageops::crop_imm(&binary, x, y, w, h).to_image());
+    tree.objects()
+        .into_iter()
+        .max_by_key(|&i| tree.contours[i].points.len())
+        .map(|i| tree.euler_number(i))
+}
+
+/// Named class of the largest shape inside the given rectangle.
+pub fn classify_reference(img: &DynamicImage, rect: (u32, u32, u32, u32), prep: &Preprocess) -> ShapeClass {
+    let binary = binarize(img, prep);
+    reference_object(&binary, rect).map_or(ShapeClass::Other, |(c, _)| {
+        classify(&ShapeFeatures::from_contour(&c.points))
+    })
+}
+
+/// Number of shapes of each class in the whole image.
+pub fn count_by_class(img: &DynamicImage, prep: &Preprocess) -> BTreeMap<ShapeClass, u32> {
+    let mut counts = BTreeMap::new();
+    for c in outer_contours(&binarize(img, prep)) {
+        *counts.entry(classify(&ShapeFeatures::from_contour(&c.points))).or_insert(0) += 1;
+    }
+    counts
+}
+
+/// How a contour is compared with the reference shape.
+#[derive(Clone, Copy, Debug)]
+pub enum ShapeMatch {
+    /// Same number of contour points.
+    PointCount,
+    /// Hu moment distance at most the given value; scale and rotation
+    /// invariant.
+    Hu(f64),
+    /// Fourier descriptor distance at most the given value; invariant to
+    /// translation, rotation, scale and starting point.
+    Fourier(f64),
+}
+
+impl ShapeMatch {
+    /// Parse `points`, `hu:<max distance>` or `fourier:<max distance>`.
+    /// A bare `hu` uses distance 0.5 and a bare `fourier` 0.1.
+    pub fn parse(spec: &str) -> Option<Self> {
+        let (kind, arg) = spec.split_once(':').unwrap_or((spec, ""));
+        match kind {
+            "points" => Some(ShapeMatch::PointCount),
+            "hu" if arg.is_empty() => Some(ShapeMatch::Hu(0.5)),
+            "hu" => arg.parse().ok().map(ShapeMatch::Hu),
+            "fourier" if arg.is_empty() => Some(ShapeMatch::Fourier(0.1)),
+            "fourier" => arg.parse().ok().map(ShapeMatch::Fourier),
+            _ => None,
+        }
+    }
+}
+
+/// Count shapes matching the reference shape inside the rectangle.
+/// With `hole_tol` set, the hole count of a shape may differ from the
+/// reference by at most that many, so a ring and a disc are told apart.
+pub fn count_same_shape(
+    img: &DynamicImage,
+    rect: (u32, u32, u32, u32),
+    prep: &Preprocess,
+    method: &ShapeMatch,
+    hole_tol: Option<u64>,
+) -> u32 {
     // Plaintext version kept for comparison
-    let gray = img.to_luma8();
-    let ref_sides = detect_shape(&gray, rect);
-    let contours = find_contours::<u8>(&gray, BorderType::Outer);
-    contours.iter().filter(|c| c.len() == ref_sides).count() as u32
+    let binary = binarize(img, prep);
+    let Some((reference, ref_holes)) = reference_object(&binary, rect) else {
+        return 0;
+    };
+    let contours: Vec<Contour<i32>> = objects(&binary)
+        .into_iter()
+        .filter(|(_, holes)| hole_tol.is_none_or(|t| holes.abs_diff(ref_holes) as u64 <= t))
+        .map(|(c, _)| c)
+        .collect();
+    match method {
+        ShapeMatch::PointCount => {
+            let ref_sides = reference.points.len();
+            contours.iter().filter(|c| c.points.len() == ref_sides).count() as u32
+        }
+        ShapeMatch::Hu(max_distance) => {
+            let ref_hu = hu_moments(&region_moments(&reference.points));
+            contours
+                .iter()
+                .filter(|c| hu_distance(&hu_moments(&region_moments(&c.points)), &ref_hu) <= *max_distance)
+                .count() as u32
+        }
+        ShapeMatch::Fourier(max_distance) => {
+            let ref_fd = fourier_descriptor(&reference.points, DEFAULT_HARMONICS);
+            contours
+                .iter()
+                .filter(|c| {
+                    descriptor_distance(&fourier_descriptor(&c.points, DEFAULT_HARMONICS), &ref_fd)
+                        <= *max_distance
+                })
+                .count() as u32
+        }
+    }
 }
 
-/// Attempt to count shapes homomorphically by comparing contour length.
-/// Only the comparison and accumulation are done under FHE to keep the
-/// example simple.
-pub fn count_same_shape_fhe(
+/// Fourier descriptors of every outer contour, with their bounding boxes,
+/// ready for `export_descriptors`.
+pub fn contour_descriptors(img: &DynamicImage, prep: &Preprocess) -> Vec<ContourDescriptor> {
+    outer_contours(&binarize(img, prep))
+        .iter()
+        .enumerate()
+        .map(|(i, c)| {
+            let min_x = c.points.iter().map(|p| p.x).min().unwrap_or(0);
+            let min_y = c.points.iter().map(|p| p.y).min().unwrap_or(0);
+            let max_x = c.points.iter().map(|p| p.x).max().unwrap_or(0);
+            let max_y = c.points.iter().map(|p| p.y).max().unwrap_or(0);
+            ContourDescriptor {
+                index: i,
+                bbox: (min_x, min_y, max_x - min_x + 1, max_y - min_y + 1),
+                descriptor: fourier_descriptor(&c.points, DEFAULT_HARMONICS),
+            }
+        })
+        .collect()
+}
+
+/// Count shapes homomorphically by comparing encrypted feature vectors.
+/// The client encrypts the features of every object and an interval around
+/// the reference; the server sums the objects inside it. Hole counts are
+/// compared only when `tol.holes` is set.
+pub fn count_same_shape_encrypted(
     img: &DynamicImage,
     rect: (u32, u32, u32, u32),
+    prep: &Preprocess,
+    tol: &FeatureTolerance,
     client_key: &ClientKey,
     server_key: &ServerKey,
-) -> u32 {
-    let gray = img.to_luma8();
-    let ref_sides = detect_shape(&gray, rect) as u64;
-    let ref_ct = client_key.encrypt(ref_sides);
-    let mut count_ct = client_key.encrypt(0u64);
-    let contours = find_contours::<u8>(&gray, BorderType::Outer);
-    for c in contours {
-        let len_ct = client_key.encrypt(c.len() as u64);
-        let eq = server_key.eq(&len_ct, &ref_ct);
-        count_ct = server_key.add(&count_ct, &eq);
-    }
-    client_key.decrypt(&count_ct) as u32
+) -> EncryptedCount {
+    let binary = binarize(img, prep);
+    // Hole counts stay zero on both sides when they are not compared
+    let describe = |c: &Contour<i32>, holes: usize| ShapeFeatures {
+        holes: if tol.holes.is_some() { holes } else { 0 },
+        ..ShapeFeatures::from_contour(&c.points)
+    };
+    let reference = reference_object(&binary, rect)
+        .map(|(c, holes)| describe(&c, holes))
+        .unwrap_or_default();
+    // Perimeters stay below four times the pixel count, the largest feature
+    let num_blocks = counter_blocks(4 * binary.width() as u64 * binary.height() as u64, server_key);
+    let range = encrypt_range(&reference, tol, num_blocks, client_key);
+
+    let match_bits: Vec<Ciphertext> = objects(&binary)
+        .iter()
+        .map(|(c, holes)| {
+            let features = encrypt_features(&describe(c, *holes), num_blocks, client_key);
+            features_in_range(&features, &range, server_key)
+        })
+        .collect();
+    sum_bits(&match_bits, server_key)
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn binarization_rejects_bad_arguments() {
+        assert!(matches!(Binarization::parse("adaptive"), Some(Binarization::AdaptiveMean(7))));
+        assert!(matches!(Binarization::parse("adaptive:3"), Some(Binarization::AdaptiveMean(3))));
+        assert!(Binarization::parse("adaptive:x").is_none());
+        assert!(Binarization::parse("fixed:x").is_none());
+    }
+
+    #[test]
+    fn shape_match_rejects_bad_distances() {
+        assert!(matches!(ShapeMatch::parse("points"), Some(ShapeMatch::PointCount)));
+        assert!(matches!(ShapeMatch::parse("hu"), Some(ShapeMatch::Hu(d)) if d == 0.5));
+        assert!(matches!(ShapeMatch::parse("hu:0.25"), Some(ShapeMatch::Hu(d)) if d == 0.25));
+        assert!(ShapeMatch::parse("hu:x").is_none());
+        assert!(matches!(ShapeMatch::parse("fourier"), Some(ShapeMatch::Fourier(d)) if d == 0.1));
+        assert!(matches!(ShapeMatch::parse("fourier:0.3"), Some(ShapeMatch::Fourier(d)) if d == 0.3));
+        assert!(ShapeMatch::parse("fourier:0.1x").is_none());
+        assert!(ShapeMatch::parse("area").is_none());
+    }
+}
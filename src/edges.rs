@@ -0,0 +1,47 @@
+use rayon::prelude::*;
+use tfhe::shortint::{Ciphertext, ServerKey};
+
+use crate::encrypt_image::EncryptedImage;
+use crate::filter::{filter_gray, BorderMode, EncryptedKernel};
+use crate::gray::{to_luma, EncryptedGrayImage, LumaWeights};
+use crate::mask::EncryptedMask;
+
+/// Encrypted Sobel gradient magnitude, approximated as `|Gx| + |Gy|` and
+/// saturated at the top of the message space.
+pub fn sobel_magnitude(gray: &EncryptedGrayImage, border: BorderMode, server_key: &ServerKey) -> EncryptedGrayImage {
+    let gx = filter_gray(gray, &EncryptedKernel::sobel_x(), border, server_key);
+    let gy = filter_gray(gray, &EncryptedKernel::sobel_y(), border, server_key);
+    let m = server_key.message_modulus.0;
+    let sat_add = server_key.generate_lookup_table_bivariate(move |a, b| (a + b).min(m - 1));
+    let data = gx
+        .data
+        .par_iter()
+        .zip(gy.data.par_iter())
+        .map(|(a, b)| server_key.apply_lookup_table_bivariate(a, b, &sat_add))
+        .collect();
+    EncryptedGrayImage {
+        width: gray.width,
+        height: gray.height,
+        data,
+    }
+}
+
+/// Encrypted edge map: pixels whose gradient magnitude reaches the encrypted
+/// threshold.
+pub fn sobel_edges(
+    enc_img: &EncryptedImage,
+    thr: &Ciphertext,
+    border: BorderMode,
+    server_key: &ServerKey,
+) -> EncryptedMask {
+    let gray = to_luma(enc_img, &LumaWeights::default(), server_key);
+    let magnitude = sobel_magnitude(&gray, border, server_key);
+    let bits = magnitude.data.par_iter().map(|px| server_key.ge(px, thr)).collect();
+    EncryptedMask::new(gray.width, gray.height, bits)
+}
+
+/// Encrypted Laplacian response of the luma channel.
+pub fn laplacian(enc_img: &EncryptedImage, border: BorderMode, server_key: &ServerKey) -> EncryptedGrayImage {
+    let gray = to_luma(enc_img, &LumaWeights::default(), server_key);
+    filter_gray(&gray, &EncryptedKernel::laplacian(), border, server_key)
+}
@@ -1,18 +1,68 @@
 use image::{DynamicImage, GenericImageView};
 use rayon::prelude::*;
-use tfhe::prelude::*;
-use tfhe::shortint::{Ciphertext, ClientKey, ConfigBuilder, ServerKey};
+use tfhe::shortint::parameters::{
+    PARAM_MESSAGE_1_CARRY_1_KS_PBS, PARAM_MESSAGE_2_CARRY_2_KS_PBS, PARAM_MESSAGE_3_CARRY_3_KS_PBS,
+    PARAM_MESSAGE_4_CARRY_4_KS_PBS,
+};
+use tfhe::shortint::{gen_keys, Ciphertext, ClientKey, ServerKey};
 
 /// Structure holding the encrypted blocks.
 /// Each block remembers its position within the original image so
 /// that the blocks can later be merged back into a full image.
+/// `x`, `y`, `width` and `height` describe the pixels the block owns. A block
+/// may also carry a halo of border pixels owned by its neighbours, so that
+/// neighbourhood operations can run on it independently; `data` then covers
+/// the larger `data_*` region.
 #[derive(Clone)]
 pub struct EncryptedBlock {
     pub x: u32,
     pub y: u32,
     pub width: u32,
     pub height: u32,
-    pub data: Vec<Ciphertext>,
+    /// Requested halo on each side; clipped at the image borders.
+    pub halo: u32,
+    pub data_x: u32,
+    pub data_y: u32,
+    pub data_width: u32,
+    pub data_height: u32,
+    pub data: Vec<Ciphertext>, // RGB data of the data region, row major
+}
+
+impl EncryptedBlock {
+    /// Block without halo whose data covers exactly the owned pixels.
+    pub fn new(x: u32, y: u32, width: u32, height: u32, data: Vec<Ciphertext>) -> Self {
+        EncryptedBlock {
+            x,
+            y,
+            width,
+            height,
+            halo: 0,
+            data_x: x,
+            data_y: y,
+            data_width: width,
+            data_height: height,
+            data,
+        }
+    }
+
+    /// Whether the image pixel `(x, y)` belongs to this block.
+    pub fn is_owned(&self, x: u32, y: u32) -> bool {
+        x >= self.x && y >= self.y && x < self.x + self.width && y < self.y + self.height
+    }
+
+    /// Whether the image pixel `(x, y)` is stored in this block, halo included.
+    pub fn contains(&self, x: u32, y: u32) -> bool {
+        x >= self.data_x
+            && y >= self.data_y
+            && x < self.data_x + self.data_width
+            && y < self.data_y + self.data_height
+    }
+
+    /// RGB ciphertexts of the stored image pixel `(x, y)`.
+    pub fn pixel(&self, x: u32, y: u32) -> &[Ciphertext] {
+        let off = (((y - self.data_y) * self.data_width + (x - self.data_x)) * 3) as usize;
+        &self.data[off..off + 3]
+    }
 }
 
 /// Encrypted image reconstructed from individual blocks.
@@ -22,13 +72,82 @@ pub struct EncryptedImage {
     pub data: Vec<Ciphertext>, // RGB data flattened row major
 }
 
+impl EncryptedImage {
+    /// Server side: encrypted sub-image of the rectangle, clipped to the
+    /// image. Ciphertexts are copied, so no key is needed.
+    pub fn crop(&self, x: u32, y: u32, width: u32, height: u32) -> EncryptedImage {
+        let (x, w) = clip_span(0, self.width, x, width);
+        let (y, h) = clip_span(0, self.height, y, height);
+        let mut data = Vec::with_capacity((w * h * 3) as usize);
+        for py in y..y + h {
+            let start = ((py * self.width + x) * 3) as usize;
+            data.extend_from_slice(&self.data[start..start + (w * 3) as usize]);
+        }
+        EncryptedImage { width: w, height: h, data }
+    }
+}
+
+/// Intersection of the span `start..start + len` with `lo..lo + size`, as
+/// its start and length. Disjoint spans give length 0.
+pub fn clip_span(start: u32, len: u32, lo: u32, size: u32) -> (u32, u32) {
+    let (a, b) = (start.max(lo), (start + len).min(lo + size));
+    (a, b.saturating_sub(a))
+}
+
+/// Server side: cut the blocks down to the rectangle `(x, y, w, h)`.
+/// Blocks that do not own any pixel of it are dropped; the others keep only
+/// the owned and halo pixels inside it. Coordinates are rebased so that
+/// `(x, y)` becomes the origin, and the result merges into a `w` by `h`
+/// image.
+pub fn crop_blocks(blocks: &[EncryptedBlock], rect: (u32, u32, u32, u32)) -> Vec<EncryptedBlock> {
+    let (x, y, w, h) = rect;
+    blocks
+        .iter()
+        .filter_map(|block| {
+            let (own_x, own_w) = clip_span(block.x, block.width, x, w);
+            let (own_y, own_h) = clip_span(block.y, block.height, y, h);
+            if own_w == 0 || own_h == 0 {
+                return None;
+            }
+            let (data_x, data_w) = clip_span(block.data_x, block.data_width, x, w);
+            let (data_y, data_h) = clip_span(block.data_y, block.data_height, y, h);
+            let mut data = Vec::with_capacity((data_w * data_h * 3) as usize);
+            for py in data_y..data_y + data_h {
+                for px in data_x..data_x + data_w {
+                    data.extend_from_slice(block.pixel(px, py));
+                }
+            }
+            Some(EncryptedBlock {
+                x: own_x - x,
+                y: own_y - y,
+                width: own_w,
+                height: own_h,
+                halo: block.halo,
+                data_x: data_x - x,
+                data_y: data_y - y,
+                data_width: data_w,
+                data_height: data_h,
+                data,
+            })
+        })
+        .collect()
+}
+
+/// Reduce an 8 bit channel value to its `bits` most significant bits.
+pub fn quantize(c: u8, bits: u32) -> u64 {
+    u64::from(c) >> (8 - bits)
+}
+
 /// Encrypt the image using TFHE and return encrypted blocks.
-/// The image is divided into blocks of `block_size` pixels.
-/// The last blocks on the edges may be smaller if the image size is not
-/// a multiple of `block_size`.
-pub fn encrypt_image(
+/// The image is divided into blocks of `block_size` pixels, keeping `bits`
+/// bits per channel. The last blocks on the edges may be smaller if the image
+/// size is not a multiple of `block_size`. Every block also stores up to
+/// `halo` pixels of its neighbours on each side.
+pub fn encrypt_image_with_halo(
     img: &DynamicImage,
     block_size: u32,
+    bits: u32,
+    halo: u32,
     client_key: &ClientKey,
 ) -> Vec<EncryptedBlock> {
     let (width, height) = img.dimensions();
@@ -46,11 +165,16 @@ pub fn encrypt_image(
             let mut block_pixels = Vec::new();
             let h = (y + block_size).min(height) - y;
             let w = (x + block_size).min(width) - x;
-            for j in 0..h {
-                for i in 0..w {
-                    let pixel = img.get_pixel(x + i, y + j);
-                    for c in pixel.0 {
-                        block_pixels.push(client_key.encrypt(u64::from(c)));
+            let data_x = x.saturating_sub(halo);
+            let data_y = y.saturating_sub(halo);
+            let data_w = (x + w + halo).min(width) - data_x;
+            let data_h = (y + h + halo).min(height) - data_y;
+            for j in 0..data_h {
+                for i in 0..data_w {
+                    let pixel = img.get_pixel(data_x + i, data_y + j);
+                    // get_pixel yields RGBA; blocks store three channels per pixel
+                    for &c in &pixel.0[..3] {
+                        block_pixels.push(client_key.encrypt(quantize(c, bits)));
                     }
                 }
             }
@@ -59,6 +183,11 @@ pub fn encrypt_image(
                 y,
                 width: w,
                 height: h,
+                halo,
+                data_x,
+                data_y,
+                data_width: data_w,
+                data_height: data_h,
                 data: block_pixels,
             }
         })
@@ -83,10 +212,8 @@ pub fn merge_encrypted_blocks(
             for bx in 0..block.width {
                 let img_x = block.x + bx;
                 let img_y = block.y + by;
-                let src_off = ((by * block.width + bx) * 3) as usize;
                 let dst_off = ((img_y * width + img_x) * 3) as usize;
-                data[dst_off..dst_off + 3]
-                    .clone_from_slice(&block.data[src_off..src_off + 3]);
+                data[dst_off..dst_off + 3].clone_from_slice(block.pixel(img_x, img_y));
             }
         }
     }
@@ -94,10 +221,38 @@ pub fn merge_encrypted_blocks(
     EncryptedImage { width, height, data }
 }
 
-/// Simple helper to create TFHE keys
-pub fn create_keys() -> (ClientKey, ServerKey) {
-    let config = ConfigBuilder::all_disabled().enable_default_uint4().build();
-    let client_key = ClientKey::generate(config);
-    let server_key = ServerKey::generate(&client_key);
-    (client_key, server_key)
+/// Create TFHE keys whose message space holds `message_bits` bits, using the
+/// smallest parameter set that fits. Returns `None` above 4 bits, the largest
+/// profile available.
+pub fn create_keys(message_bits: u32) -> Option<(ClientKey, ServerKey)> {
+    let keys = match message_bits {
+        0 | 1 => gen_keys(PARAM_MESSAGE_1_CARRY_1_KS_PBS),
+        2 => gen_keys(PARAM_MESSAGE_2_CARRY_2_KS_PBS),
+        3 => gen_keys(PARAM_MESSAGE_3_CARRY_3_KS_PBS),
+        4 => gen_keys(PARAM_MESSAGE_4_CARRY_4_KS_PBS),
+        _ => return None,
+    };
+    Some(keys)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clip_span_intersects() {
+        assert_eq!(clip_span(0, 10, 3, 4), (3, 4));
+        assert_eq!(clip_span(5, 10, 0, 8), (5, 3));
+        assert_eq!(clip_span(2, 3, 0, 100), (2, 3));
+        // Disjoint spans, on either side
+        assert_eq!(clip_span(0, 4, 6, 2).1, 0);
+        assert_eq!(clip_span(8, 4, 0, 8).1, 0);
+    }
+
+    #[test]
+    fn clip_span_on_image_edges() {
+        // A rectangle reaching past the image keeps only the inside part
+        assert_eq!(clip_span(0, 20, 15, 10), (15, 5));
+        assert_eq!(clip_span(0, 20, 25, 10).1, 0);
+    }
 }
@@ -0,0 +1,106 @@
+use tfhe::integer::{ClientKey as RadixClientKey, RadixCiphertext};
+use tfhe::shortint::{Ciphertext, ClientKey, ServerKey};
+
+use crate::pixel_count::radix_server_key;
+use crate::shape_class::ShapeFeatures;
+
+/// Tolerances for comparing shape features.
+/// Area and perimeter are relative to the reference, vertex count and
+/// circularity are absolute. Hole counts are only compared when `holes` is
+/// set, and may then differ by at most that many.
+#[derive(Clone, Copy, Debug)]
+pub struct FeatureTolerance {
+    pub area: f64,
+    pub perimeter: f64,
+    pub vertices: u64,
+    pub circularity: f64,
+    pub holes: Option<u64>,
+}
+
+impl Default for FeatureTolerance {
+    fn default() -> Self {
+        FeatureTolerance {
+            area: 0.2,
+            perimeter: 0.2,
+            vertices: 0,
+            circularity: 0.1,
+            holes: None,
+        }
+    }
+}
+
+/// Fixed point scale applied to circularity before encryption.
+const CIRCULARITY_SCALE: f64 = 100.0;
+
+/// Integer form of the compared features: area, perimeter, vertex count,
+/// circularity in hundredths, hole count.
+fn quantized(f: &ShapeFeatures) -> [u64; 5] {
+    [
+        f.area.round() as u64,
+        f.perimeter.round() as u64,
+        f.vertices as u64,
+        (f.circularity * CIRCULARITY_SCALE).round() as u64,
+        f.holes as u64,
+    ]
+}
+
+/// Encrypted feature vector of one contour.
+pub struct EncryptedFeatures(pub [RadixCiphertext; 5]);
+
+/// Encrypted acceptance interval `[lo, hi]` of every feature.
+pub struct EncryptedFeatureRange {
+    pub lo: [RadixCiphertext; 5],
+    pub hi: [RadixCiphertext; 5],
+}
+
+/// Client side: encrypt the features of a contour.
+pub fn encrypt_features(f: &ShapeFeatures, num_blocks: usize, client_key: &ClientKey) -> EncryptedFeatures {
+    let radix_key = RadixClientKey::from(client_key.clone());
+    EncryptedFeatures(quantized(f).map(|v| radix_key.encrypt_radix(v, num_blocks)))
+}
+
+/// Client side: encrypt the acceptance interval around the reference
+/// features, so the server never sees the reference or the tolerance.
+pub fn encrypt_range(
+    reference: &ShapeFeatures,
+    tol: &FeatureTolerance,
+    num_blocks: usize,
+    client_key: &ClientKey,
+) -> EncryptedFeatureRange {
+    let radix_key = RadixClientKey::from(client_key.clone());
+    let [area, perimeter, vertices, circularity, holes] = quantized(reference);
+    let rel = |v: u64, t: f64| (v as f64 * t).round() as u64;
+    let slack = [
+        rel(area, tol.area),
+        rel(perimeter, tol.perimeter),
+        tol.vertices,
+        (tol.circularity * CIRCULARITY_SCALE).round() as u64,
+        tol.holes.unwrap_or(0),
+    ];
+    let values = [area, perimeter, vertices, circularity, holes];
+    let lo: [u64; 5] = std::array::from_fn(|i| values[i].saturating_sub(slack[i]));
+    let hi: [u64; 5] = std::array::from_fn(|i| values[i] + slack[i]);
+    EncryptedFeatureRange {
+        lo: lo.map(|v| radix_key.encrypt_radix(v, num_blocks)),
+        hi: hi.map(|v| radix_key.encrypt_radix(v, num_blocks)),
+    }
+}
+
+/// Server side: encrypted bit that is set when every feature lies in its
+/// interval.
+pub fn features_in_range(
+    features: &EncryptedFeatures,
+    range: &EncryptedFeatureRange,
+    server_key: &ServerKey,
+) -> Ciphertext {
+    let radix_key = radix_server_key(server_key);
+    let all = (0..features.0.len())
+        .map(|i| {
+            let above = radix_key.ge_parallelized(&features.0[i], &range.lo[i]);
+            let below = radix_key.le_parallelized(&features.0[i], &range.hi[i]);
+            radix_key.boolean_bitand(&above, &below)
+        })
+        .reduce(|a, b| radix_key.boolean_bitand(&a, &b))
+        .unwrap();
+    all.into_raw_parts()
+}
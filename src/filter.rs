@@ -0,0 +1,321 @@
+use rayon::prelude::*;
+use tfhe::integer::{RadixCiphertext, ServerKey as RadixServerKey};
+use tfhe::shortint::{Ciphertext, ServerKey};
+
+use crate::encrypt_image::EncryptedBlock;
+use crate::gray::EncryptedGrayImage;
+use crate::pixel_count::{counter_blocks, radix_server_key, widen};
+
+/// How pixels outside the image are treated by neighbourhood operations.
+#[derive(Clone, Copy, Debug)]
+pub enum BorderMode {
+    /// Outside pixels are zero, so their taps are skipped.
+    Zero,
+    /// Outside pixels repeat the nearest edge pixel.
+    Replicate,
+}
+
+impl BorderMode {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "zero" => Some(BorderMode::Zero),
+            "replicate" => Some(BorderMode::Replicate),
+            _ => None,
+        }
+    }
+
+    /// Map a neighbour coordinate to an in-bounds one, or `None` for a zero tap.
+    pub fn resolve(&self, x: i32, y: i32, width: u32, height: u32) -> Option<(u32, u32)> {
+        let inside = x >= 0 && y >= 0 && x < width as i32 && y < height as i32;
+        match self {
+            _ if inside => Some((x as u32, y as u32)),
+            BorderMode::Zero => None,
+            BorderMode::Replicate => Some((
+                x.clamp(0, width as i32 - 1) as u32,
+                y.clamp(0, height as i32 - 1) as u32,
+            )),
+        }
+    }
+}
+
+/// What to do with a negative kernel response.
+#[derive(Clone, Copy, Debug)]
+pub enum Response {
+    /// Clamp to zero, as for blur and sharpening.
+    Clamp,
+    /// Take the absolute value, as for edge detectors.
+    Abs,
+}
+
+/// Small integer kernel applied as `sum(w * x) / divisor`.
+/// The kernel is centred, so `width` and `height` must be odd.
+#[derive(Clone, Debug)]
+pub struct EncryptedKernel {
+    pub width: u32,
+    pub height: u32,
+    /// Row major weights.
+    pub weights: Vec<i32>,
+    /// Normalizing divisor applied once to the weighted sum.
+    pub divisor: u64,
+    pub response: Response,
+}
+
+impl EncryptedKernel {
+    pub fn new(width: u32, height: u32, weights: Vec<i32>, divisor: u64, response: Response) -> Self {
+        assert!(width % 2 == 1 && height % 2 == 1, "kernel size must be odd");
+        assert_eq!(weights.len(), (width * height) as usize, "kernel size mismatch");
+        assert!(divisor > 0, "kernel divisor must be positive");
+        EncryptedKernel {
+            width,
+            height,
+            weights,
+            divisor,
+            response,
+        }
+    }
+
+    /// Box filter of side `2 * radius + 1`, i.e. the mean of its taps.
+    pub fn box_filter(radius: u32) -> Self {
+        let side = 2 * radius + 1;
+        let taps = side * side;
+        Self::new(side, side, vec![1; taps as usize], u64::from(taps), Response::Clamp)
+    }
+
+    /// 3x3 Gaussian blur.
+    pub fn gaussian3() -> Self {
+        Self::new(3, 3, vec![1, 2, 1, 2, 4, 2, 1, 2, 1], 16, Response::Clamp)
+    }
+
+    /// 3x3 sharpening filter.
+    pub fn sharpen() -> Self {
+        Self::new(3, 3, vec![0, -1, 0, -1, 5, -1, 0, -1, 0], 1, Response::Clamp)
+    }
+
+    pub fn sobel_x() -> Self {
+        Self::new(3, 3, vec![-1, 0, 1, -2, 0, 2, -1, 0, 1], 4, Response::Abs)
+    }
+
+    pub fn sobel_y() -> Self {
+        Self::new(3, 3, vec![-1, -2, -1, 0, 0, 0, 1, 2, 1], 4, Response::Abs)
+    }
+
+    pub fn laplacian() -> Self {
+        Self::new(3, 3, vec![0, 1, 0, 1, -4, 1, 0, 1, 0], 4, Response::Abs)
+    }
+
+    /// Parse `box:<r>`, `gaussian`, `sharpen`, `sobel-x`, `sobel-y` or
+    /// `laplacian`.
+    pub fn parse(spec: &str) -> Option<Self> {
+        let (kind, arg) = spec.split_once(':').unwrap_or((spec, "1"));
+        match kind {
+            "box" => arg.parse().ok().map(Self::box_filter),
+            "gaussian" => Some(Self::gaussian3()),
+            "sharpen" => Some(Self::sharpen()),
+            "sobel-x" => Some(Self::sobel_x()),
+            "sobel-y" => Some(Self::sobel_y()),
+            "laplacian" => Some(Self::laplacian()),
+            _ => None,
+        }
+    }
+
+    /// Half extent of the kernel, i.e. the halo a tile needs on each side.
+    pub fn radius(&self) -> (u32, u32) {
+        (self.width / 2, self.height / 2)
+    }
+}
+
+/// Taps of one kernel, prepared once and shared by every pixel.
+/// Positive and negative weighted taps are summed undivided in radix
+/// accumulators wide enough for the full response, and the divisor is applied
+/// once to the combined sum, as in the plaintext formula.
+pub struct KernelTaps {
+    taps: Vec<(i32, i32, bool, u64)>,
+    response: Response,
+    divisor: u64,
+    num_blocks: usize,
+    radix_key: RadixServerKey,
+}
+
+impl KernelTaps {
+    pub fn new(kernel: &EncryptedKernel, server_key: &ServerKey) -> Self {
+        let max_value = server_key.message_modulus.0 - 1;
+        let (rx, ry) = kernel.radius();
+        let mut taps = Vec::new();
+        for ky in 0..kernel.height {
+            for kx in 0..kernel.width {
+                let w = kernel.weights[(ky * kernel.width + kx) as usize];
+                if w == 0 {
+                    continue;
+                }
+                taps.push((kx as i32 - rx as i32, ky as i32 - ry as i32, w > 0, w.unsigned_abs() as u64));
+            }
+        }
+        let max_sum = kernel.weights.iter().map(|w| w.unsigned_abs() as u64).sum::<u64>() * max_value;
+        KernelTaps {
+            taps,
+            response: kernel.response,
+            divisor: kernel.divisor,
+            num_blocks: counter_blocks(max_sum, server_key),
+            radix_key: radix_server_key(server_key),
+        }
+    }
+
+    /// Kernel response at one pixel. `fetch(dx, dy)` returns the neighbour at
+    /// that offset, or `None` when the tap falls on a zero border.
+    pub fn apply<'a, F>(&self, fetch: F, server_key: &ServerKey) -> Ciphertext
+    where
+        F: Fn(i32, i32) -> Option<&'a Ciphertext>,
+    {
+        let radix_key = &self.radix_key;
+        let mut pos: Vec<RadixCiphertext> = Vec::new();
+        let mut neg: Vec<RadixCiphertext> = Vec::new();
+        for &(dx, dy, positive, w) in &self.taps {
+            let Some(px) = fetch(dx, dy) else {
+                continue;
+            };
+            let term = radix_key.scalar_mul_parallelized(&widen(px, self.num_blocks, radix_key), w);
+            if positive { &mut pos } else { &mut neg }.push(term);
+        }
+        let sum = |terms: &[RadixCiphertext]| {
+            radix_key
+                .sum_ciphertexts_parallelized(terms.iter())
+                .unwrap_or_else(|| radix_key.create_trivial_zero_radix(self.num_blocks))
+        };
+        let (pos, neg) = (sum(&pos), sum(&neg));
+        let hi = radix_key.max_parallelized(&pos, &neg);
+        let response = match self.response {
+            // max(pos, neg) - neg is pos - neg clamped at zero
+            Response::Clamp => radix_key.sub_parallelized(&hi, &neg),
+            Response::Abs => radix_key.sub_parallelized(&hi, &radix_key.min_parallelized(&pos, &neg)),
+        };
+        let scaled = radix_key.scalar_div_parallelized(&response, self.divisor);
+        let max_value = server_key.message_modulus.0 - 1;
+        radix_key.scalar_min_parallelized(&scaled, max_value).blocks()[0].clone()
+    }
+}
+
+/// Apply a kernel to an encrypted gray image.
+pub fn filter_gray(
+    gray: &EncryptedGrayImage,
+    kernel: &EncryptedKernel,
+    border: BorderMode,
+    server_key: &ServerKey,
+) -> EncryptedGrayImage {
+    let taps = KernelTaps::new(kernel, server_key);
+    let data = (0..gray.width * gray.height)
+        .into_par_iter()
+        .map(|idx| {
+            let x = (idx % gray.width) as i32;
+            let y = (idx / gray.width) as i32;
+            taps.apply(
+                |dx, dy| {
+                    border
+                        .resolve(x + dx, y + dy, gray.width, gray.height)
+                        .map(|(nx, ny)| gray.get(nx, ny))
+                },
+                server_key,
+            )
+        })
+        .collect();
+    EncryptedGrayImage {
+        width: gray.width,
+        height: gray.height,
+        data,
+    }
+}
+
+/// Index of the block owning each pixel of a `width` x `height` image, in
+/// row major order. Built from the owned rectangles, so it also holds for
+/// blocks that do not sit on a regular grid, such as crops.
+fn owner_index(blocks: &[EncryptedBlock], width: u32, height: u32) -> Vec<usize> {
+    let mut owners = vec![usize::MAX; (width * height) as usize];
+    for (i, block) in blocks.iter().enumerate() {
+        for y in block.y..block.y + block.height {
+            let row = (y * width) as usize;
+            owners[row + block.x as usize..row + (block.x + block.width) as usize].fill(i);
+        }
+    }
+    owners
+}
+
+/// RGB of image pixel `(x, y)`, from the block itself or from its owner.
+fn halo_pixel<'a>(
+    block: &'a EncryptedBlock,
+    blocks: &'a [EncryptedBlock],
+    owners: &[usize],
+    width: u32,
+    x: u32,
+    y: u32,
+) -> &'a [Ciphertext] {
+    if block.contains(x, y) {
+        return block.pixel(x, y);
+    }
+    let owner = &blocks[owners[(y * width + x) as usize]];
+    debug_assert!(owner.is_owned(x, y));
+    owner.pixel(x, y)
+}
+
+/// Apply a kernel to every channel of the encrypted blocks, one block per
+/// task. Taps that fall in a block's own halo are served locally; any
+/// remaining halo rows and columns are pulled from the neighbouring blocks,
+/// so no full image is ever assembled. The result blocks carry no halo.
+pub fn filter_blocks(
+    blocks: &[EncryptedBlock],
+    width: u32,
+    height: u32,
+    kernel: &EncryptedKernel,
+    border: BorderMode,
+    server_key: &ServerKey,
+) -> Vec<EncryptedBlock> {
+    let taps = KernelTaps::new(kernel, server_key);
+    let owners = owner_index(blocks, width, height);
+
+    blocks
+        .par_iter()
+        .map(|block| {
+            let mut data = Vec::with_capacity((block.width * block.height * 3) as usize);
+            for by in 0..block.height {
+                for bx in 0..block.width {
+                    let x = (block.x + bx) as i32;
+                    let y = (block.y + by) as i32;
+                    for c in 0..3 {
+                        data.push(taps.apply(
+                            |dx, dy| {
+                                border
+                                    .resolve(x + dx, y + dy, width, height)
+                                    .map(|(nx, ny)| &halo_pixel(block, blocks, &owners, width, nx, ny)[c])
+                            },
+                            server_key,
+                        ));
+                    }
+                }
+            }
+            EncryptedBlock::new(block.x, block.y, block.width, block.height, data)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kernels_divide_by_their_weight() {
+        // Smoothing kernels preserve a flat image, so divide by their weight sum
+        for kernel in [EncryptedKernel::box_filter(1), EncryptedKernel::box_filter(2), EncryptedKernel::gaussian3()] {
+            assert_eq!(kernel.weights.iter().sum::<i32>() as u64, kernel.divisor);
+        }
+        assert_eq!(EncryptedKernel::parse("box:1").unwrap().divisor, 9);
+    }
+
+    #[test]
+    fn owner_index_follows_uneven_blocks() {
+        // A 7x2 image split into a 3-wide and a 4-wide column, as after a crop
+        let blocks = [
+            EncryptedBlock::new(0, 0, 3, 2, Vec::new()),
+            EncryptedBlock::new(3, 0, 4, 2, Vec::new()),
+        ];
+        let owners = owner_index(&blocks, 7, 2);
+        assert_eq!(owners, [0, 0, 0, 1, 1, 1, 1, 0, 0, 0, 1, 1, 1, 1]);
+    }
+}
@@ -0,0 +1,148 @@
+use std::f64::consts::PI;
+use std::fs;
+
+use imageproc::point::Point;
+use serde_json::json;
+
+/// Number of harmonics kept in a descriptor by default.
+pub const DEFAULT_HARMONICS: usize = 16;
+
+/// Descriptor of one contour together with where it was found.
+#[derive(Clone, Debug)]
+pub struct ContourDescriptor {
+    pub index: usize,
+    /// Bounding box `(x, y, w, h)` in image coordinates.
+    pub bbox: (i32, i32, i32, i32),
+    pub descriptor: Vec<f64>,
+}
+
+/// Resample a closed contour to `n` points evenly spaced along its length.
+fn resample(points: &[Point<i32>], n: usize) -> Vec<(f64, f64)> {
+    let pts: Vec<(f64, f64)> = points.iter().map(|p| (p.x as f64, p.y as f64)).collect();
+    let seg_len = |i: usize| {
+        let (a, b) = (pts[i], pts[(i + 1) % pts.len()]);
+        ((b.0 - a.0).powi(2) + (b.1 - a.1).powi(2)).sqrt()
+    };
+    let total: f64 = (0..pts.len()).map(seg_len).sum();
+    if total == 0.0 {
+        return vec![pts[0]; n];
+    }
+
+    let step = total / n as f64;
+    let mut out = Vec::with_capacity(n);
+    let (mut seg, mut seg_start) = (0, 0.0);
+    for k in 0..n {
+        let target = k as f64 * step;
+        while seg + 1 < pts.len() && seg_start + seg_len(seg) < target {
+            seg_start += seg_len(seg);
+            seg += 1;
+        }
+        let (a, b) = (pts[seg], pts[(seg + 1) % pts.len()]);
+        let len = seg_len(seg);
+        let t = if len == 0.0 { 0.0 } else { (target - seg_start) / len };
+        out.push((a.0 + t * (b.0 - a.0), a.1 + t * (b.1 - a.1)));
+    }
+    out
+}
+
+/// Normalized Fourier descriptor of a closed contour.
+/// The contour is treated as the complex signal `x + iy`; dropping the DC
+/// term removes translation, dividing by the first harmonic removes scale,
+/// and keeping only magnitudes removes rotation and the starting point.
+/// Returns `harmonics` values, or zeros for degenerate contours.
+pub fn fourier_descriptor(points: &[Point<i32>], harmonics: usize) -> Vec<f64> {
+    if points.len() < 3 {
+        return vec![0.0; harmonics];
+    }
+    let n = (2 * harmonics + 2).max(64);
+    let z = resample(points, n);
+    let magnitude = |k: usize| {
+        let (mut re, mut im) = (0.0, 0.0);
+        for (j, &(x, y)) in z.iter().enumerate() {
+            let angle = -2.0 * PI * (k * j) as f64 / n as f64;
+            re += x * angle.cos() - y * angle.sin();
+            im += x * angle.sin() + y * angle.cos();
+        }
+        (re * re + im * im).sqrt()
+    };
+    // Combine positive and negative frequencies so direction does not matter
+    let coeffs: Vec<f64> = (1..=harmonics + 1)
+        .map(|k| magnitude(k) + magnitude(n - k))
+        .collect();
+    let scale = coeffs[0];
+    if scale == 0.0 {
+        return vec![0.0; harmonics];
+    }
+    coeffs[1..].iter().map(|c| c / scale).collect()
+}
+
+/// Euclidean distance between two descriptors.
+pub fn descriptor_distance(a: &[f64], b: &[f64]) -> f64 {
+    a.iter()
+        .zip(b)
+        .map(|(x, y)| (x - y).powi(2))
+        .sum::<f64>()
+        .sqrt()
+}
+
+/// Save descriptors as JSON for offline clustering. Each entry keeps the
+/// contour index and its bounding box so clusters can be traced back.
+pub fn export_descriptors(path: &str, entries: &[ContourDescriptor]) -> std::io::Result<()> {
+    let rows: Vec<_> = entries
+        .iter()
+        .map(|e| {
+            let (x, y, w, h) = e.bbox;
+            json!({
+                "index": e.index,
+                "bbox": { "x": x, "y": y, "w": w, "h": h },
+                "descriptor": e.descriptor,
+            })
+        })
+        .collect();
+    fs::write(path, serde_json::to_string_pretty(&rows).unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Closed outline of an irregular pentagon, rotated and scaled about the
+    /// origin and shifted into positive coordinates.
+    fn outline(angle: f64, scale: f64, start: usize) -> Vec<Point<i32>> {
+        let corners = [(0.0, 0.0), (80.0, 10.0), (100.0, 60.0), (40.0, 90.0), (-10.0, 50.0)];
+        let (sin, cos) = angle.sin_cos();
+        let mut points: Vec<Point<i32>> = corners
+            .iter()
+            .map(|&(x, y)| {
+                let (x, y) = (scale * (x * cos - y * sin), scale * (x * sin + y * cos));
+                Point::new(x.round() as i32 + 300, y.round() as i32 + 300)
+            })
+            .collect();
+        points.rotate_left(start);
+        points
+    }
+
+    #[test]
+    fn descriptor_ignores_rotation_scale_and_start() {
+        let reference = fourier_descriptor(&outline(0.0, 1.0, 0), DEFAULT_HARMONICS);
+        for (angle, scale, start) in [(0.7, 1.0, 0), (0.0, 2.0, 0), (0.0, 1.0, 2), (2.0, 1.5, 3)] {
+            let d = descriptor_distance(&reference, &fourier_descriptor(&outline(angle, scale, start), DEFAULT_HARMONICS));
+            assert!(d < 0.05, "angle {} scale {} start {}: distance {}", angle, scale, start, d);
+        }
+    }
+
+    #[test]
+    fn descriptor_separates_shapes() {
+        let square = [Point::new(0, 0), Point::new(100, 0), Point::new(100, 100), Point::new(0, 100)];
+        let d = descriptor_distance(
+            &fourier_descriptor(&outline(0.0, 1.0, 0), DEFAULT_HARMONICS),
+            &fourier_descriptor(&square, DEFAULT_HARMONICS),
+        );
+        assert!(d > 0.05, "distance {}", d);
+    }
+
+    #[test]
+    fn degenerate_contour_gives_zeros() {
+        assert_eq!(fourier_descriptor(&[Point::new(1, 1), Point::new(2, 2)], 4), vec![0.0; 4]);
+    }
+}
@@ -0,0 +1,86 @@
+use image::GrayImage;
+use rayon::prelude::*;
+use tfhe::shortint::{Ciphertext, ClientKey, ServerKey};
+
+use crate::encrypt_image::EncryptedImage;
+use crate::pixel_count::{counter_blocks, radix_server_key, widen};
+
+/// Encrypted single channel image, one ciphertext per pixel in row major
+/// order.
+#[derive(Clone)]
+pub struct EncryptedGrayImage {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<Ciphertext>,
+}
+
+impl EncryptedGrayImage {
+    /// Encrypted value at `(x, y)`.
+    pub fn get(&self, x: u32, y: u32) -> &Ciphertext {
+        &self.data[(y * self.width + x) as usize]
+    }
+}
+
+/// Integer luma weights applied as `(r * wr + g * wg + b * wb) >> shift`.
+#[derive(Clone, Copy, Debug)]
+pub struct LumaWeights {
+    pub r: u64,
+    pub g: u64,
+    pub b: u64,
+    pub shift: u32,
+}
+
+impl Default for LumaWeights {
+    /// Rec. 709 weights scaled to 256, the same ones `to_luma8` uses.
+    fn default() -> Self {
+        LumaWeights {
+            r: 54,
+            g: 183,
+            b: 19,
+            shift: 8,
+        }
+    }
+}
+
+/// Homomorphic luma conversion.
+/// The weighted channels are summed unshifted in a radix accumulator wide
+/// enough for the full product, then shifted once, so the result rounds like
+/// the plaintext formula instead of truncating every channel separately.
+pub fn to_luma(enc_img: &EncryptedImage, weights: &LumaWeights, server_key: &ServerKey) -> EncryptedGrayImage {
+    let radix_key = radix_server_key(server_key);
+    let max_value = server_key.message_modulus.0 - 1;
+    let num_blocks = counter_blocks(max_value * (weights.r + weights.g + weights.b), server_key);
+    let data = enc_img
+        .data
+        .par_chunks(3)
+        .map(|px| {
+            let terms: Vec<_> = px
+                .iter()
+                .zip([weights.r, weights.g, weights.b])
+                .map(|(c, w)| radix_key.scalar_mul_parallelized(&widen(c, num_blocks, &radix_key), w))
+                .collect();
+            let sum = radix_key
+                .sum_ciphertexts_parallelized(terms.iter())
+                .expect("three channels per pixel");
+            let luma = radix_key.scalar_right_shift_parallelized(&sum, weights.shift as u64);
+            // Weights summing above 1 << shift could exceed one block
+            radix_key.scalar_min_parallelized(&luma, max_value).blocks()[0].clone()
+        })
+        .collect();
+    EncryptedGrayImage {
+        width: enc_img.width,
+        height: enc_img.height,
+        data,
+    }
+}
+
+/// Client side: decrypt an encrypted gray image whose levels have `bits`
+/// bits, scaling them back to 8 bit.
+pub fn decrypt_gray(img: &EncryptedGrayImage, bits: u32, client_key: &ClientKey) -> GrayImage {
+    let pixels: Vec<u8> = img
+        .data
+        .par_iter()
+        .map(|c| (client_key.decrypt(c) << (8 - bits)) as u8)
+        .collect();
+    GrayImage::from_raw(img.width, img.height, pixels).expect("gray image size mismatch")
+}
@@ -0,0 +1,80 @@
+use image::GrayImage;
+use imageproc::contours::{find_contours, BorderType, Contour};
+
+/// Contours of a binary image with their parent/child links.
+/// Outer borders of objects have hole borders as children, and objects
+/// nested inside a hole are children of that hole.
+pub struct ContourTree {
+    pub contours: Vec<Contour<i32>>,
+    pub children: Vec<Vec<usize>>,
+}
+
+impl ContourTree {
+    pub fn new(img: &GrayImage) -> Self {
+        let contours = find_contours::<i32>(img);
+        let mut children = vec![Vec::new(); contours.len()];
+        for (i, c) in contours.iter().enumerate() {
+            if let Some(parent) = c.parent {
+                children[parent].push(i);
+            }
+        }
+        ContourTree { contours, children }
+    }
+
+    /// Indices of the outer borders, one per object.
+    pub fn objects(&self) -> Vec<usize> {
+        (0..self.contours.len())
+            .filter(|&i| self.contours[i].border_type == BorderType::Outer)
+            .collect()
+    }
+
+    /// Outer borders with their number of holes, moving the contours out of
+    /// the tree instead of copying their points.
+    pub fn into_objects(self) -> Vec<(Contour<i32>, usize)> {
+        let holes: Vec<usize> = (0..self.contours.len()).map(|i| self.holes(i)).collect();
+        self.contours
+            .into_iter()
+            .zip(holes)
+            .filter(|(c, _)| c.border_type == BorderType::Outer)
+            .collect()
+    }
+
+    /// Number of holes directly inside the object with outer border `i`.
+    pub fn holes(&self, i: usize) -> usize {
+        self.children[i]
+            .iter()
+            .filter(|&&c| self.contours[c].border_type == BorderType::Hole)
+            .count()
+    }
+
+    /// Euler number of the object: one component minus its holes.
+    pub fn euler_number(&self, i: usize) -> i32 {
+        1 - self.holes(i) as i32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Luma;
+    use imageproc::drawing::draw_filled_circle_mut;
+
+    #[test]
+    fn ring_and_disc_differ_in_holes() {
+        let mut disc = GrayImage::new(40, 40);
+        draw_filled_circle_mut(&mut disc, (20, 20), 12, Luma([255u8]));
+        let mut ring = disc.clone();
+        draw_filled_circle_mut(&mut ring, (20, 20), 6, Luma([0u8]));
+
+        let disc = ContourTree::new(&disc);
+        let ring = ContourTree::new(&ring);
+        let (d, r) = (disc.objects(), ring.objects());
+        assert_eq!((d.len(), r.len()), (1, 1));
+        assert_eq!((disc.holes(d[0]), disc.euler_number(d[0])), (0, 1));
+        assert_eq!((ring.holes(r[0]), ring.euler_number(r[0])), (1, 0));
+
+        let objects = ring.into_objects();
+        assert_eq!(objects.len(), 1);
+        assert_eq!((objects[0].0.border_type, objects[0].1), (BorderType::Outer, 1));
+    }
+}
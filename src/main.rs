@@ -3,24 +3,219 @@ use std::io::Read;
 use std::process::Command;
 
 use image::GenericImageView;
+use tfhe::shortint::{ClientKey, ServerKey};
 
+mod edges;
 mod encrypt_image;
-use serde_json;
+mod color_chroma;
+mod color_hsv;
+mod color_shape;
 mod count_rgb;
 mod count_shape;
-use encrypt_image::{create_keys, encrypt_image, merge_encrypted_blocks};
-use count_rgb::count_rgb_objects;
-use count_shape::{count_same_shape, count_same_shape_fhe};
+mod feature_match;
+mod filter;
+mod fourier;
+mod gray;
+mod hierarchy;
+mod mask;
+mod mask_shape;
+mod moments;
+mod morphology;
+mod oblivious_roi;
+mod packed_eq;
+mod pixel_count;
+mod privacy;
+mod release_policy;
+mod shape_class;
+mod shape_library;
+mod template;
+mod threshold;
+use encrypt_image::{create_keys, crop_blocks, encrypt_image_with_halo, merge_encrypted_blocks, quantize};
+use color_chroma::ChromaTolerance;
+use color_hsv::HsvTolerance;
+use color_shape::count_color_shape;
+use count_rgb::{count_mask_objects, rgb_match_mask, ColorMode};
+use gray::{decrypt_gray, to_luma, LumaWeights};
+use threshold::{threshold_fixed, threshold_otsu};
+use edges::{laplacian, sobel_edges};
+use filter::{filter_blocks, BorderMode, EncryptedKernel};
+use mask::{decrypt_region, save_mask_overlay};
+use template::{count_template_matches, TemplateMetric};
+use shape_library::{count_library_encrypted, load_library};
+use mask_shape::{count_mask_shapes, encrypt_object_range, object_features};
+use count_shape::{
+    classify_reference, contour_descriptors, count_by_class, count_same_shape, count_same_shape_encrypted,
+    reference_euler_number, Binarization, Preprocess, ShapeMatch,
+};
+use feature_match::FeatureTolerance;
+use fourier::export_descriptors;
+use oblivious_roi::{count_in_roi, encrypt_roi, roi_center_color, roi_mask};
+use morphology::{MorphOp, StructuringElement};
+use pixel_count::{count_matched_pixels, counter_blocks, decrypt_count, EncryptedCount};
+use privacy::{image_id, DpConfig, DpMechanism, DpRelease, PrivacyLedger};
+use release_policy::{gate_count, release_gated, release_value, Released};
+
+/// Return the value following `--name` on the command line, if any.
+fn flag_value<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|a| a == name)
+        .and_then(|i| args.get(i + 1))
+        .map(String::as_str)
+}
+
+/// DP sensitivity of counters of objects: one object changes them by one.
+const OBJECT_SENSITIVITY: f64 = 1.0;
+
+/// Decrypt a counter, going through the DP layer when it is enabled.
+fn decrypt_with_dp(
+    dp: &mut Option<DpRelease>,
+    count: &EncryptedCount,
+    sensitivity: f64,
+    client_key: &ClientKey,
+    server_key: &ServerKey,
+) -> u64 {
+    match dp {
+        Some(dp) => dp.release(count, sensitivity, client_key, server_key).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }),
+        None => decrypt_count(count, client_key),
+    }
+}
+
+/// Release a counter under the optional minimum-count policy and DP layer.
+/// `sensitivity` is how much one object can change the counter.
+fn release_count(
+    dp: &mut Option<DpRelease>,
+    min_count: Option<u64>,
+    count: &EncryptedCount,
+    sensitivity: f64,
+    client_key: &ClientKey,
+    server_key: &ServerKey,
+) -> Released {
+    match min_count {
+        Some(k) => {
+            let gated = gate_count(count, k, server_key);
+            release_gated(&gated, client_key, |c| {
+                decrypt_with_dp(dp, c, sensitivity, client_key, server_key)
+            })
+        }
+        None => Released::Count(decrypt_with_dp(dp, count, sensitivity, client_key, server_key)),
+    }
+}
+
+/// Release an object count the client computed in plaintext under the same
+/// policy.
+fn release_plain(dp: &mut Option<DpRelease>, min_count: Option<u64>, value: u64) -> Released {
+    release_value(value, min_count, |v| match dp {
+        Some(dp) => dp.release_value(v, OBJECT_SENSITIVITY).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }),
+        None => v,
+    })
+}
 
 fn main() {
     // Parse arguments
     let args: Vec<String> = std::env::args().collect();
     if args.len() < 2 {
-        eprintln!("Usage: cargo run -- <image_path> [block_size]");
+        eprintln!(
+            "Usage: cargo run -- <image_path> [block_size] [--morph open,close] [--se square:1|cross:1|grid:010/111/010] \\
+             [--mask-out mask.png] [--pixel-count] [--dp-epsilon 0.5] \\
+             [--dp-mechanism laplace|geometric] [--dp-budget 1.0] [--dp-ledger ledger.json] \\
+             [--dp-pixel-sensitivity 400] \\
+             [--min-count k] [--color-mode exact|hsv|chroma|packed] [--hue-window 20] [--min-sat 0.2] \\
+             [--min-val 0.2] [--chroma-tol 0.05] [--channel-bits 4] \\
+             [--binarize otsu|adaptive:7|fixed:128|none] [--invert] \\
+             [--filter box:1|gaussian|sharpen] [--halo 1] [--border replicate|zero] \\
+             [--gray-out gray.png] [--gray-threshold otsu|128] [--edges sobel:64|laplacian:32] \\
+             [--shape-match points|hu:0.5|fourier:0.1] [--export-descriptors fd.json] \\
+             [--area-tol 0.2] [--perimeter-tol 0.2] [--vertex-tol 0] [--circularity-tol 0.1] [--hole-tol 0] \\
+             [--mask-shapes] [--max-object-size 8] [--color-shape] [--library shapes.json|shapes.svg] \\
+             [--template sad:200|ssd:400] [--nms-radius r] [--crop-roi] [--oblivious-roi]"
+        );
         return;
     }
     let img_path = &args[1];
     let block_size: u32 = args.get(2).and_then(|s| s.parse().ok()).unwrap_or(10);
+    let se = flag_value(&args, "--se")
+        .map(|s| StructuringElement::parse(s).expect("invalid structuring element"))
+        .unwrap_or_else(|| StructuringElement::square(1));
+    let morph: Vec<MorphOp> = flag_value(&args, "--morph")
+        .map(|s| {
+            s.split(',')
+                .map(|op| MorphOp::parse(op, &se).expect("unknown morphological operation"))
+                .collect()
+        })
+        .unwrap_or_default();
+    let prep = Preprocess {
+        binarization: flag_value(&args, "--binarize")
+            .map(|b| Binarization::parse(b).expect("unknown binarization"))
+            .unwrap_or(Preprocess::default().binarization),
+        invert: args.iter().any(|a| a == "--invert"),
+    };
+    let defaults = FeatureTolerance::default();
+    let feature_tol = FeatureTolerance {
+        area: flag_value(&args, "--area-tol")
+            .map(|v| v.parse().expect("invalid area tolerance"))
+            .unwrap_or(defaults.area),
+        perimeter: flag_value(&args, "--perimeter-tol")
+            .map(|v| v.parse().expect("invalid perimeter tolerance"))
+            .unwrap_or(defaults.perimeter),
+        vertices: flag_value(&args, "--vertex-tol")
+            .map(|v| v.parse().expect("invalid vertex tolerance"))
+            .unwrap_or(defaults.vertices),
+        circularity: flag_value(&args, "--circularity-tol")
+            .map(|v| v.parse().expect("invalid circularity tolerance"))
+            .unwrap_or(defaults.circularity),
+        holes: flag_value(&args, "--hole-tol").map(|v| v.parse().expect("invalid hole tolerance")),
+    };
+    // Packed equality needs a whole pixel in one message, so three channels
+    // share the 4 bits of the largest key profile
+    let packed = flag_value(&args, "--color-mode") == Some("packed");
+    let channel_bits: u32 = flag_value(&args, "--channel-bits")
+        .map(|b| b.parse().expect("invalid channel bits"))
+        .unwrap_or(if packed { 1 } else { 4 });
+    if !(1..=4).contains(&channel_bits) {
+        eprintln!("--channel-bits は 1 から 4 の範囲で指定してください: {}", channel_bits);
+        return;
+    }
+    let color_mode = match flag_value(&args, "--color-mode").unwrap_or("exact") {
+        "exact" => ColorMode::Exact,
+        "hsv" => {
+            let defaults = HsvTolerance::default();
+            ColorMode::Hsv(HsvTolerance {
+                hue_window: flag_value(&args, "--hue-window")
+                    .map(|v| v.parse().expect("invalid hue window"))
+                    .unwrap_or(defaults.hue_window),
+                min_saturation: flag_value(&args, "--min-sat")
+                    .map(|v| v.parse().expect("invalid saturation floor"))
+                    .unwrap_or(defaults.min_saturation),
+                min_value: flag_value(&args, "--min-val")
+                    .map(|v| v.parse().expect("invalid value floor"))
+                    .unwrap_or(defaults.min_value),
+            })
+        }
+        "chroma" => ColorMode::Chromaticity(ChromaTolerance {
+            tolerance: flag_value(&args, "--chroma-tol")
+                .map(|v| v.parse().expect("invalid chromaticity tolerance"))
+                .unwrap_or(ChromaTolerance::default().tolerance),
+        }),
+        "packed" => ColorMode::Packed(channel_bits),
+        other => panic!("unknown color mode: {}", other),
+    };
+
+    // Plaintext crops and window sizes would reveal the rectangle the
+    // oblivious mode hides
+    if args.iter().any(|a| a == "--oblivious-roi") {
+        for flag in ["--crop-roi", "--template"] {
+            if args.iter().any(|a| a == flag) {
+                eprintln!("{} と --oblivious-roi は同時に指定できません", flag);
+                return;
+            }
+        }
+    }
 
     // Call Python script for region selection
     let _ = Command::new("python3")
@@ -41,32 +236,287 @@ fn main() {
     let h = sel["h"].as_u64().unwrap() as u32;
 
     let img = image::open(img_path).expect("cannot open image");
-    let (client_key, server_key) = create_keys();
+    if w == 0 || h == 0 || x + w > img.width() || y + h > img.height() {
+        eprintln!(
+            "選択領域 ({}, {}, {}, {}) が画像 ({}x{}) の外にはみ出しています",
+            x, y, w, h, img.width(), img.height()
+        );
+        return;
+    }
+    let message_bits = if packed { 3 * channel_bits } else { channel_bits };
+    let Some((client_key, server_key)) = create_keys(message_bits) else {
+        eprintln!(
+            "{}ビットのメッセージ空間に対応する鍵がありません。--channel-bits を小さくしてください",
+            message_bits
+        );
+        return;
+    };
+    // "Same RGB value" below means equal after quantization
+    println!("色は各チャンネル上位{}ビットに量子化して比較します", channel_bits);
+
+    // Optional differential privacy layer for released counters
+    let mut dp = flag_value(&args, "--dp-epsilon").map(|eps| DpRelease {
+        config: DpConfig {
+            epsilon: eps.parse().expect("invalid epsilon"),
+            mechanism: flag_value(&args, "--dp-mechanism")
+                .map(|m| DpMechanism::parse(m).expect("unknown DP mechanism"))
+                .unwrap_or(DpMechanism::Geometric),
+        },
+        budget: flag_value(&args, "--dp-budget")
+            .map(|b| b.parse().expect("invalid privacy budget"))
+            .unwrap_or(1.0),
+        ledger: PrivacyLedger::load(flag_value(&args, "--dp-ledger").unwrap_or("privacy_ledger.json"))
+            .unwrap_or_else(|e| {
+                eprintln!("プライバシー台帳を読み込めません: {}", e);
+                std::process::exit(1);
+            }),
+        image_id: image_id(&img),
+    });
+    // Pixel totals change by a whole object's area when one object changes,
+    // so DP on them needs that bound from the user
+    let pixel_sensitivity: Option<f64> = flag_value(&args, "--dp-pixel-sensitivity")
+        .map(|s| s.parse().expect("invalid pixel sensitivity"));
+    let pixel_counters = ["--pixel-count", "--edges", "--crop-roi", "--oblivious-roi"];
+    if dp.is_some() && pixel_sensitivity.is_none() && args.iter().any(|a| pixel_counters.contains(&a.as_str())) {
+        eprintln!(
+            "{} の画素数にDPを適用するには --dp-pixel-sensitivity (物体1つの最大面積) を指定してください",
+            pixel_counters.join("/")
+        );
+        return;
+    }
+    // Only read when DP is enabled, and then it was given explicitly
+    let pixel_sensitivity = pixel_sensitivity.unwrap_or(OBJECT_SENSITIVITY);
+    // Counts below this minimum are reported only as "below threshold"
+    let min_count: Option<u64> = flag_value(&args, "--min-count")
+        .map(|k| k.parse().expect("invalid minimum count"));
 
-    // Encrypt image in blocks
-    let blocks = encrypt_image(&img, block_size, &client_key);
+    // Encrypt image in blocks, optionally filtered on the server block by
+    // block; by default each block carries enough halo to be filtered alone
+    let border = flag_value(&args, "--border")
+        .map(|b| BorderMode::parse(b).expect("unknown border mode"))
+        .unwrap_or(BorderMode::Replicate);
+    let kernel = flag_value(&args, "--filter")
+        .map(|spec| EncryptedKernel::parse(spec).expect("unknown filter"));
+    let halo: u32 = flag_value(&args, "--halo")
+        .map(|h| h.parse().expect("invalid halo"))
+        .unwrap_or_else(|| kernel.as_ref().map_or(0, |k| k.radius().0.max(k.radius().1)));
+    let blocks = encrypt_image_with_halo(&img, block_size, channel_bits, halo, &client_key);
+    let blocks = match &kernel {
+        Some(kernel) => {
+            filter_blocks(&blocks, img.width(), img.height(), kernel, border, &server_key)
+        }
+        None => blocks,
+    };
     // Merge blocks back into full encrypted image for analysis
     let enc_img = merge_encrypted_blocks(&blocks, img.width(), img.height(), &client_key);
 
-    // Reference color (center pixel of selected region)
+    // Server-side luma conversion, decrypted only for inspection
+    if let Some(path) = flag_value(&args, "--gray-out") {
+        let gray = to_luma(&enc_img, &LumaWeights::default(), &server_key);
+        decrypt_gray(&gray, channel_bits, &client_key)
+            .save(path)
+            .expect("failed to save gray image");
+    }
+
+    // Objects of the luma image binarized on the server, colour ignored
+    if let Some(spec) = flag_value(&args, "--gray-threshold") {
+        let gray = to_luma(&enc_img, &LumaWeights::default(), &server_key);
+        let binary = match spec {
+            "otsu" => threshold_otsu(&gray, &server_key),
+            level => {
+                let level: u8 = level.parse().expect("invalid gray threshold");
+                threshold_fixed(&gray, &client_key.encrypt(quantize(level, channel_bits)), &server_key)
+            }
+        };
+        println!(
+            "輝度で二値化({})した画像の中の物体は{}含まれています",
+            spec,
+            release_plain(&mut dp, min_count, count_mask_objects(&binary, &client_key) as u64)
+        );
+    }
+
+    // Edge pixels of the luma image, detected on the server
+    if let Some(spec) = flag_value(&args, "--edges") {
+        let (kind, level) = spec.split_once(':').expect("edge spec must be sobel:<level> or laplacian:<level>");
+        let level: u8 = level.parse().expect("invalid edge threshold");
+        let thr = client_key.encrypt(quantize(level, channel_bits));
+        let edge_mask = match kind {
+            "sobel" => sobel_edges(&enc_img, &thr, border, &server_key),
+            "laplacian" => threshold_fixed(&laplacian(&enc_img, border, &server_key), &thr, &server_key),
+            other => panic!("unknown edge detector: {}", other),
+        };
+        let edge_ct = count_matched_pixels(&edge_mask, &server_key);
+        println!(
+            "エッジ({})と判定された画素は{}含まれています",
+            spec,
+            release_count(&mut dp, min_count, &edge_ct, pixel_sensitivity, &client_key, &server_key)
+        );
+    }
+
+    // Reference color (center pixel of selected region). With an oblivious
+    // ROI the server picks it using the encrypted bounds.
     let cx = x + w / 2;
     let cy = y + h / 2;
     let ref_pixel = img.get_pixel(cx, cy);
-    let ref_rgb = [
-        client_key.encrypt(u64::from(ref_pixel[0])),
-        client_key.encrypt(u64::from(ref_pixel[1])),
-        client_key.encrypt(u64::from(ref_pixel[2])),
-    ];
+    let oblivious_roi = args.iter().any(|a| a == "--oblivious-roi").then(|| {
+        let num_blocks = counter_blocks(2 * img.width().max(img.height()) as u64, &server_key);
+        encrypt_roi((x, y, w, h), num_blocks, &client_key)
+    });
+    let ref_rgb = match &oblivious_roi {
+        Some(roi) => roi_center_color(&enc_img, roi, &server_key),
+        None => [
+            client_key.encrypt(quantize(ref_pixel[0], channel_bits)),
+            client_key.encrypt(quantize(ref_pixel[1], channel_bits)),
+            client_key.encrypt(quantize(ref_pixel[2], channel_bits)),
+        ],
+    };
+
+    // Server builds the encrypted match mask; the client decides what to release
+    let mask = rgb_match_mask(&enc_img, &ref_rgb, &color_mode, &morph, &server_key);
+    if let Some(path) = flag_value(&args, "--mask-out") {
+        save_mask_overlay(&mask, &img, path, &client_key).expect("failed to save mask");
+    }
+    let rgb_count = count_mask_objects(&mask, &client_key);
+
+    // Objects of the reference colour that also have the selected shape
+    if args.iter().any(|a| a == "--color-shape") {
+        match count_color_shape(&mask, (cx, cy), &client_key) {
+            Some(counts) => {
+                println!(
+                    "画像の中に、ユーザが選択した物体と同じRGB値で同じ形({})の物体は{}含まれています",
+                    counts.target,
+                    release_plain(&mut dp, min_count, counts.matching as u64)
+                );
+                for (class, count) in &counts.by_class {
+                    println!("  {}: {}", class, release_plain(&mut dp, min_count, *count as u64));
+                }
+            }
+            None => eprintln!("警告: 選択した画素が参照色の物体に含まれていないため、形を判定できません"),
+        }
+    }
+
+    // Objects of the matched colour with the area and perimeter of the
+    // selected one, measured on the encrypted mask
+    if args.iter().any(|a| a == "--mask-shapes") {
+        // Objects are measured within this many pixels, bounding the work
+        let max_object_size: u32 = flag_value(&args, "--max-object-size")
+            .map(|v| v.parse().expect("invalid maximum object size"))
+            .unwrap_or(8);
+        if max_object_size == 0 {
+            eprintln!("--max-object-size は 1 以上を指定してください");
+            return;
+        }
+        // Reference taken from the same mask, so colour mode, quantization
+        // and morphology apply to it as well
+        let roi_map = decrypt_region(&mask, (x, y, w, h), &client_key);
+        let reference = object_features(w, h, &roi_map)
+            .into_iter()
+            .max_by_key(|f| f.area)
+            .unwrap_or_default();
+        let num_blocks = counter_blocks(4 * img.width() as u64 * img.height() as u64, &server_key);
+        let range = encrypt_object_range(&reference, &feature_tol, num_blocks, &client_key);
+        let shape_ct = count_mask_shapes(&mask, &range, max_object_size, &server_key);
+        println!(
+            "画像の中に、ユーザが選択した物体と同じ色で同じ面積・周囲長の物体は{}含まれています",
+            release_count(&mut dp, min_count, &shape_ct, OBJECT_SENSITIVITY, &client_key, &server_key)
+        );
+    }
+
+    // Matched pixels inside the encrypted region; the server never learns it
+    if let Some(roi) = &oblivious_roi {
+        let inside = roi_mask(roi, enc_img.width, enc_img.height, &server_key);
+        let roi_ct = count_in_roi(&mask, &inside, &server_key);
+        println!(
+            "秘匿された選択領域の中に、ユーザが選択した物体と同じRGB値の画素は{}含まれています",
+            release_count(&mut dp, min_count, &roi_ct, pixel_sensitivity, &client_key, &server_key)
+        );
+    }
+
+    // Matched pixels inside the selected region only, on a server-side crop
+    if args.iter().any(|a| a == "--crop-roi") {
+        let roi_img = merge_encrypted_blocks(&crop_blocks(&blocks, (x, y, w, h)), w, h, &client_key);
+        let roi_mask = rgb_match_mask(&roi_img, &ref_rgb, &color_mode, &morph, &server_key);
+        let roi_ct = count_matched_pixels(&roi_mask, &server_key);
+        println!(
+            "選択領域の中に、ユーザが選択した物体と同じRGB値の画素は{}含まれています",
+            release_count(&mut dp, min_count, &roi_ct, pixel_sensitivity, &client_key, &server_key)
+        );
+    }
 
-    let rgb_count = count_rgb_objects(&enc_img, ref_rgb, &client_key, &server_key);
-    let shape_count = count_same_shape_fhe(&img, (x, y, w, h), &client_key, &server_key);
+    // Occurrences of the selected patch by encrypted template matching
+    if let Some(spec) = flag_value(&args, "--template") {
+        let metric = TemplateMetric::parse(spec).expect("unknown template metric");
+        let nms_radius = flag_value(&args, "--nms-radius")
+            .map(|v| v.parse().expect("invalid NMS radius"))
+            .unwrap_or(w.min(h) / 2);
+        // The selected region of the encrypted image serves as the template
+        let template = enc_img.crop(x, y, w, h);
+        let hits = count_template_matches(&enc_img, &template, &metric, nms_radius, &server_key);
+        println!(
+            "画像の中に、ユーザが選択した領域と同じ模様({})は{}含まれています",
+            spec,
+            release_count(&mut dp, min_count, &hits, OBJECT_SENSITIVITY, &client_key, &server_key)
+        );
+    }
+
+    // Matched pixel total: only the aggregate counter is decrypted
+    if args.iter().any(|a| a == "--pixel-count") {
+        let first_stage = if morph.is_empty() {
+            mask.clone()
+        } else {
+            rgb_match_mask(&enc_img, &ref_rgb, &color_mode, &[], &server_key)
+        };
+        let pixel_ct = count_matched_pixels(&first_stage, &server_key);
+        let pixel_count = release_count(&mut dp, min_count, &pixel_ct, pixel_sensitivity, &client_key, &server_key);
+        println!(
+            "画像の中に、ユーザが選択した物体と同じRGB値の画素は{}含まれています",
+            pixel_count
+        );
+    }
+    let shape_ct = count_same_shape_encrypted(&img, (x, y, w, h), &prep, &feature_tol, &client_key, &server_key);
+    let shape_count = release_count(&mut dp, min_count, &shape_ct, OBJECT_SENSITIVITY, &client_key, &server_key);
 
     println!(
-        "画像の中に、ユーザが選択した物体と同じRGB値の物体は{}個含まれています",
-        rgb_count
+        "画像の中に、ユーザが選択した物体と同じRGB値の物体は{}含まれています",
+        release_plain(&mut dp, min_count, rgb_count as u64)
     );
     println!(
-        "この画像の中に、ユーザが指定した物体と同じ形のものは{}個含まれています",
+        "この画像の中に、ユーザが指定した物体と同じ形のものは{}含まれています",
         shape_count
     );
+
+    // Named class of the selected object and per-class counts
+    println!(
+        "ユーザが指定した物体の形は「{}」と判定されました",
+        classify_reference(&img, (x, y, w, h), &prep)
+    );
+    for (class, count) in count_by_class(&img, &prep) {
+        println!("  {}: {}", class, release_plain(&mut dp, min_count, count as u64));
+    }
+    if let Some(euler) = reference_euler_number(&img, (x, y, w, h), &prep) {
+        println!("ユーザが指定した物体のオイラー数は{}(穴{}個)です", euler, 1 - euler);
+    }
+
+    // Plaintext shape matching with an invariant method, for comparison
+    if let Some(spec) = flag_value(&args, "--shape-match") {
+        let method = ShapeMatch::parse(spec).expect("unknown shape match method");
+        let matched = count_same_shape(&img, (x, y, w, h), &prep, &method, feature_tol.holes);
+        println!(
+            "この画像の中に、ユーザが指定した物体と同じ形({})のものは{}含まれています",
+            spec,
+            release_plain(&mut dp, min_count, matched as u64)
+        );
+    }
+    // Matches for every entry of a reference shape library, grouped by name
+    if let Some(path) = flag_value(&args, "--library") {
+        let library = load_library(path).expect("failed to load shape library");
+        println!("参照形状ライブラリ({})と同じ形の物体の数:", path);
+        for (name, count) in count_library_encrypted(&img, &prep, &library, &client_key, &server_key) {
+            let released = release_count(&mut dp, min_count, &count, OBJECT_SENSITIVITY, &client_key, &server_key);
+            println!("  {}: {}", name, released);
+        }
+    }
+    if let Some(path) = flag_value(&args, "--export-descriptors") {
+        export_descriptors(path, &contour_descriptors(&img, &prep)).expect("failed to export descriptors");
+    }
 }
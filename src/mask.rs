@@ -0,0 +1,69 @@
+use image::{DynamicImage, GrayImage, Luma, Rgba, RgbaImage};
+use rayon::prelude::*;
+use tfhe::shortint::{Ciphertext, ClientKey};
+
+/// Encrypted boolean map produced by the server.
+/// Each pixel holds an encrypted 0/1 bit, flattened row major, so the mask
+/// can be shipped back to the client without revealing anything.
+#[derive(Clone)]
+pub struct EncryptedMask {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<Ciphertext>,
+}
+
+impl EncryptedMask {
+    pub fn new(width: u32, height: u32, data: Vec<Ciphertext>) -> Self {
+        assert_eq!(data.len(), (width * height) as usize, "mask size mismatch");
+        EncryptedMask { width, height, data }
+    }
+}
+
+/// Client side: decrypt the mask into a gray image (255 = set, 0 = unset).
+pub fn decrypt_mask(mask: &EncryptedMask, client_key: &ClientKey) -> GrayImage {
+    let pixels: Vec<u8> = mask
+        .data
+        .par_iter()
+        .map(|c| if client_key.decrypt(c) != 0 { 255 } else { 0 })
+        .collect();
+    GrayImage::from_raw(mask.width, mask.height, pixels).expect("mask size mismatch")
+}
+
+/// Convert a decrypted mask into the boolean map used by plaintext labeling.
+pub fn mask_to_bools(mask: &GrayImage) -> Vec<bool> {
+    mask.pixels().map(|p| p[0] != 0).collect()
+}
+
+/// Client side: decrypt only the rectangle `(x, y, w, h)` of the mask into a
+/// boolean map of its own size.
+pub fn decrypt_region(mask: &EncryptedMask, rect: (u32, u32, u32, u32), client_key: &ClientKey) -> Vec<bool> {
+    let (x, y, w, h) = rect;
+    (0..w * h)
+        .into_par_iter()
+        .map(|i| client_key.decrypt(&mask.data[((y + i / w) * mask.width + x + i % w) as usize]) != 0)
+        .collect()
+}
+
+/// Client side: blend the decrypted mask over the original image in red.
+pub fn mask_overlay(img: &DynamicImage, mask: &GrayImage) -> RgbaImage {
+    let mut out = img.to_rgba8();
+    for (x, y, px) in out.enumerate_pixels_mut() {
+        let Luma([m]) = *mask.get_pixel(x, y);
+        if m != 0 {
+            let Rgba([r, g, b, a]) = *px;
+            *px = Rgba([((r as u16 + 255) / 2) as u8, g / 2, b / 2, a]);
+        }
+    }
+    out
+}
+
+/// Client side: decrypt the mask and save it as a PNG overlay on `img`.
+pub fn save_mask_overlay(
+    mask: &EncryptedMask,
+    img: &DynamicImage,
+    path: &str,
+    client_key: &ClientKey,
+) -> image::ImageResult<()> {
+    let gray = decrypt_mask(mask, client_key);
+    mask_overlay(img, &gray).save(path)
+}
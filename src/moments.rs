@@ -0,0 +1,174 @@
+use image::{GrayImage, Luma};
+use imageproc::drawing::draw_polygon_mut;
+use imageproc::point::Point;
+
+/// Area and central image moments of a filled region, up to third order.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Moments {
+    pub m00: f64,
+    pub mu20: f64,
+    pub mu11: f64,
+    pub mu02: f64,
+    pub mu30: f64,
+    pub mu21: f64,
+    pub mu12: f64,
+    pub mu03: f64,
+}
+
+/// Rasterize the region enclosed by a contour into a mask of its bounding
+/// box. Returns the mask and the image coordinates of its top-left corner.
+pub fn fill_contour(points: &[Point<i32>]) -> (GrayImage, (i32, i32)) {
+    let min_x = points.iter().map(|p| p.x).min().unwrap_or(0);
+    let min_y = points.iter().map(|p| p.y).min().unwrap_or(0);
+    let max_x = points.iter().map(|p| p.x).max().unwrap_or(0);
+    let max_y = points.iter().map(|p| p.y).max().unwrap_or(0);
+    let mut mask = GrayImage::new((max_x - min_x + 1) as u32, (max_y - min_y + 1) as u32);
+
+    let mut poly: Vec<Point<i32>> = points
+        .iter()
+        .map(|p| Point::new(p.x - min_x, p.y - min_y))
+        .collect();
+    poly.dedup();
+    while poly.len() > 1 && poly[0] == poly[poly.len() - 1] {
+        poly.pop();
+    }
+    if poly.len() >= 3 {
+        draw_polygon_mut(&mut mask, &poly, Luma([255u8]));
+    }
+    // The border itself always belongs to the region
+    for p in &poly {
+        mask.put_pixel(p.x as u32, p.y as u32, Luma([255u8]));
+    }
+    (mask, (min_x, min_y))
+}
+
+/// Moments of the region enclosed by a contour.
+pub fn region_moments(points: &[Point<i32>]) -> Moments {
+    let (mask, _) = fill_contour(points);
+    let pixels: Vec<(f64, f64)> = mask
+        .enumerate_pixels()
+        .filter(|(_, _, p)| p[0] != 0)
+        .map(|(x, y, _)| (x as f64, y as f64))
+        .collect();
+
+    let m00 = pixels.len() as f64;
+    if m00 == 0.0 {
+        return Moments::default();
+    }
+    let m10: f64 = pixels.iter().map(|p| p.0).sum();
+    let m01: f64 = pixels.iter().map(|p| p.1).sum();
+    let (cx, cy) = (m10 / m00, m01 / m00);
+    let central = |p: u32, q: u32| -> f64 {
+        pixels
+            .iter()
+            .map(|&(x, y)| (x - cx).powi(p as i32) * (y - cy).powi(q as i32))
+            .sum()
+    };
+    Moments {
+        m00,
+        mu20: central(2, 0),
+        mu11: central(1, 1),
+        mu02: central(0, 2),
+        mu30: central(3, 0),
+        mu21: central(2, 1),
+        mu12: central(1, 2),
+        mu03: central(0, 3),
+    }
+}
+
+/// Hu's seven invariant moments, invariant to translation, scale and
+/// rotation.
+pub fn hu_moments(m: &Moments) -> [f64; 7] {
+    if m.m00 == 0.0 {
+        return [0.0; 7];
+    }
+    // Scale-normalized central moments
+    let eta = |mu: f64, p: i32, q: i32| mu / m.m00.powf(1.0 + (p + q) as f64 / 2.0);
+    let n20 = eta(m.mu20, 2, 0);
+    let n11 = eta(m.mu11, 1, 1);
+    let n02 = eta(m.mu02, 0, 2);
+    let n30 = eta(m.mu30, 3, 0);
+    let n21 = eta(m.mu21, 2, 1);
+    let n12 = eta(m.mu12, 1, 2);
+    let n03 = eta(m.mu03, 0, 3);
+
+    let a = n30 + n12;
+    let b = n21 + n03;
+    [
+        n20 + n02,
+        (n20 - n02).powi(2) + 4.0 * n11.powi(2),
+        (n30 - 3.0 * n12).powi(2) + (3.0 * n21 - n03).powi(2),
+        a.powi(2) + b.powi(2),
+        (n30 - 3.0 * n12) * a * (a.powi(2) - 3.0 * b.powi(2))
+            + (3.0 * n21 - n03) * b * (3.0 * a.powi(2) - b.powi(2)),
+        (n20 - n02) * (a.powi(2) - b.powi(2)) + 4.0 * n11 * a * b,
+        (3.0 * n21 - n03) * a * (a.powi(2) - 3.0 * b.powi(2))
+            - (n30 - 3.0 * n12) * b * (3.0 * a.powi(2) - b.powi(2)),
+    ]
+}
+
+/// Invariants smaller than this fraction of the first one are numerical
+/// noise, e.g. the odd-order invariants of a symmetric shape.
+const HU_EPS: f64 = 1e-5;
+
+/// Distance between two sets of Hu moments, as OpenCV's `CONTOURS_MATCH_I2`.
+/// Each invariant is compared on a signed log scale, since their magnitudes
+/// differ by orders of magnitude. Invariants below the noise floor map to
+/// the floor itself, so two vanishing values compare equal and a vanishing
+/// value against a real one costs a bounded amount.
+pub fn hu_distance(a: &[f64; 7], b: &[f64; 7]) -> f64 {
+    let eps = HU_EPS * a[0].abs().max(b[0].abs());
+    if eps == 0.0 {
+        return 0.0;
+    }
+    let log = |h: f64| {
+        if h.abs() <= eps { eps.log10() } else { h.signum() * h.abs().log10() }
+    };
+    a.iter().zip(b).map(|(x, y)| (log(*x) - log(*y)).abs()).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transform(points: &[(f64, f64)], angle: f64, scale: f64) -> Vec<Point<i32>> {
+        let (sin, cos) = angle.sin_cos();
+        points
+            .iter()
+            .map(|&(x, y)| {
+                let (x, y) = (scale * (x * cos - y * sin), scale * (x * sin + y * cos));
+                Point::new(x.round() as i32 + 200, y.round() as i32 + 200)
+            })
+            .collect()
+    }
+
+    // An L shape has no symmetry, so every invariant is exercised
+    const L_SHAPE: [(f64, f64); 6] = [(0.0, 0.0), (60.0, 0.0), (60.0, 20.0), (20.0, 20.0), (20.0, 90.0), (0.0, 90.0)];
+
+    fn hu(points: &[Point<i32>]) -> [f64; 7] {
+        hu_moments(&region_moments(points))
+    }
+
+    #[test]
+    fn rotated_and_scaled_copy_is_close() {
+        let reference = hu(&transform(&L_SHAPE, 0.0, 1.0));
+        for (angle, scale) in [(0.5, 1.0), (std::f64::consts::FRAC_PI_2, 1.0), (1.2, 1.5)] {
+            let d = hu_distance(&reference, &hu(&transform(&L_SHAPE, angle, scale)));
+            assert!(d < 0.5, "angle {} scale {}: distance {}", angle, scale, d);
+        }
+    }
+
+    #[test]
+    fn different_shapes_are_far() {
+        let square = [(0.0, 0.0), (80.0, 0.0), (80.0, 80.0), (0.0, 80.0)];
+        let d = hu_distance(&hu(&transform(&L_SHAPE, 0.0, 1.0)), &hu(&transform(&square, 0.0, 1.0)));
+        assert!(d > 0.5, "distance {}", d);
+    }
+
+    #[test]
+    fn vanishing_invariants_compare_equal() {
+        let a = [0.2, 0.01, 1e-12, 1e-13, 0.0, 1e-20, -1e-25];
+        let b = [0.2, 0.01, -1e-14, 1e-11, 0.0, -1e-19, 1e-22];
+        assert_eq!(hu_distance(&a, &b), 0.0);
+    }
+}
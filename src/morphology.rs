@@ -0,0 +1,239 @@
+use rayon::prelude::*;
+use tfhe::shortint::{Ciphertext, ServerKey};
+
+/// Structuring element given as pixel offsets relative to the centre.
+#[derive(Clone, Debug)]
+pub struct StructuringElement {
+    pub offsets: Vec<(i32, i32)>,
+}
+
+impl StructuringElement {
+    /// Square of side `2 * radius + 1` centred on the pixel.
+    pub fn square(radius: u32) -> Self {
+        let r = radius as i32;
+        let mut offsets = Vec::new();
+        for dy in -r..=r {
+            for dx in -r..=r {
+                offsets.push((dx, dy));
+            }
+        }
+        StructuringElement { offsets }
+    }
+
+    /// Cross (plus sign) with arms of length `radius`.
+    pub fn cross(radius: u32) -> Self {
+        let r = radius as i32;
+        let mut offsets = vec![(0, 0)];
+        for d in 1..=r {
+            offsets.extend_from_slice(&[(d, 0), (-d, 0), (0, d), (0, -d)]);
+        }
+        StructuringElement { offsets }
+    }
+
+    /// Build an element from a `width` x `height` boolean grid whose centre is
+    /// the origin.
+    pub fn from_mask(width: u32, height: u32, mask: &[bool]) -> Self {
+        let (cx, cy) = ((width / 2) as i32, (height / 2) as i32);
+        let mut offsets = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                if mask[(y * width + x) as usize] {
+                    offsets.push((x as i32 - cx, y as i32 - cy));
+                }
+            }
+        }
+        StructuringElement { offsets }
+    }
+
+    /// Parse `square:<r>`, `cross:<r>` or `grid:<rows>` as used on the
+    /// command line. A grid lists its rows of `0`/`1` separated by `/`, e.g.
+    /// `grid:010/111/010`; it needs odd sides, so the centre is the origin,
+    /// and at least one set cell.
+    pub fn parse(spec: &str) -> Option<Self> {
+        let (kind, arg) = spec.split_once(':').unwrap_or((spec, "1"));
+        match kind {
+            "square" => arg.parse().ok().map(Self::square),
+            "cross" => arg.parse().ok().map(Self::cross),
+            "grid" => {
+                let rows: Vec<&str> = arg.split('/').collect();
+                let width = rows[0].len();
+                if width.is_multiple_of(2) || rows.len().is_multiple_of(2) {
+                    return None;
+                }
+                if rows.iter().any(|r| r.len() != width || r.chars().any(|c| c != '0' && c != '1')) {
+                    return None;
+                }
+                let mask: Vec<bool> = rows.iter().flat_map(|r| r.chars().map(|c| c == '1')).collect();
+                if !mask.contains(&true) {
+                    return None;
+                }
+                Some(Self::from_mask(width as u32, rows.len() as u32, &mask))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Morphological operation applied to an encrypted boolean map.
+#[derive(Clone, Debug)]
+pub enum MorphOp {
+    Erode(StructuringElement),
+    Dilate(StructuringElement),
+    /// Erosion followed by dilation: removes specks and thin bridges.
+    Open(StructuringElement),
+    /// Dilation followed by erosion: fills small holes and gaps.
+    Close(StructuringElement),
+}
+
+impl MorphOp {
+    /// Parse `open`, `close`, `erode` or `dilate` with the given element.
+    pub fn parse(name: &str, se: &StructuringElement) -> Option<Self> {
+        match name {
+            "erode" => Some(MorphOp::Erode(se.clone())),
+            "dilate" => Some(MorphOp::Dilate(se.clone())),
+            "open" => Some(MorphOp::Open(se.clone())),
+            "close" => Some(MorphOp::Close(se.clone())),
+            _ => None,
+        }
+    }
+}
+
+/// Internal: combine every pixel with its in-bounds neighbours using `op`.
+/// Pixels outside the image are skipped, which is the neutral element for
+/// both AND (erosion) and OR (dilation).
+fn reduce_neighbourhood<F>(
+    width: u32,
+    height: u32,
+    bits: &[Ciphertext],
+    offsets: &[(i32, i32)],
+    op: F,
+) -> Vec<Ciphertext>
+where
+    F: Fn(&Ciphertext, &Ciphertext) -> Ciphertext + Sync,
+{
+    (0..width * height)
+        .into_par_iter()
+        .map(|idx| {
+            let x = (idx % width) as i32;
+            let y = (idx / width) as i32;
+            let mut acc: Option<Ciphertext> = None;
+            for (dx, dy) in offsets {
+                let nx = x + dx;
+                let ny = y + dy;
+                if nx < 0 || ny < 0 || nx >= width as i32 || ny >= height as i32 {
+                    continue;
+                }
+                let n = &bits[(ny as u32 * width + nx as u32) as usize];
+                acc = Some(match acc {
+                    Some(a) => op(&a, n),
+                    None => n.clone(),
+                });
+            }
+            acc.unwrap_or_else(|| bits[idx as usize].clone())
+        })
+        .collect()
+}
+
+/// Encrypted erosion: a pixel stays set only if every neighbour under the
+/// structuring element is set.
+pub fn erode(
+    width: u32,
+    height: u32,
+    bits: &[Ciphertext],
+    se: &StructuringElement,
+    server_key: &ServerKey,
+) -> Vec<Ciphertext> {
+    reduce_neighbourhood(width, height, bits, &se.offsets, |a, b| {
+        server_key.and(a, b)
+    })
+}
+
+/// Encrypted dilation: a pixel becomes set if any neighbour under the
+/// reflected structuring element is set.
+pub fn dilate(
+    width: u32,
+    height: u32,
+    bits: &[Ciphertext],
+    se: &StructuringElement,
+    server_key: &ServerKey,
+) -> Vec<Ciphertext> {
+    let reflected: Vec<(i32, i32)> = se.offsets.iter().map(|&(dx, dy)| (-dx, -dy)).collect();
+    reduce_neighbourhood(width, height, bits, &reflected, |a, b| {
+        server_key.or(a, b)
+    })
+}
+
+/// Encrypted opening (erode then dilate).
+pub fn open(
+    width: u32,
+    height: u32,
+    bits: &[Ciphertext],
+    se: &StructuringElement,
+    server_key: &ServerKey,
+) -> Vec<Ciphertext> {
+    let eroded = erode(width, height, bits, se, server_key);
+    dilate(width, height, &eroded, se, server_key)
+}
+
+/// Encrypted closing (dilate then erode).
+pub fn close(
+    width: u32,
+    height: u32,
+    bits: &[Ciphertext],
+    se: &StructuringElement,
+    server_key: &ServerKey,
+) -> Vec<Ciphertext> {
+    let dilated = dilate(width, height, bits, se, server_key);
+    erode(width, height, &dilated, se, server_key)
+}
+
+/// Apply a sequence of morphological operations in order.
+pub fn apply_morphology(
+    width: u32,
+    height: u32,
+    bits: Vec<Ciphertext>,
+    ops: &[MorphOp],
+    server_key: &ServerKey,
+) -> Vec<Ciphertext> {
+    ops.iter().fold(bits, |bits, op| match op {
+        MorphOp::Erode(se) => erode(width, height, &bits, se, server_key),
+        MorphOp::Dilate(se) => dilate(width, height, &bits, se, server_key),
+        MorphOp::Open(se) => open(width, height, &bits, se, server_key),
+        MorphOp::Close(se) => close(width, height, &bits, se, server_key),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn offsets(spec: &str) -> Option<Vec<(i32, i32)>> {
+        StructuringElement::parse(spec).map(|se| se.offsets)
+    }
+
+    #[test]
+    fn square_and_cross() {
+        assert_eq!(offsets("square:1").unwrap().len(), 9);
+        assert_eq!(offsets("square:0"), Some(vec![(0, 0)]));
+        assert_eq!(offsets("cross:2").unwrap().len(), 9);
+        assert!(offsets("square:x").is_none());
+        assert!(offsets("disk:1").is_none());
+    }
+
+    #[test]
+    fn grid_is_centred() {
+        assert_eq!(offsets("grid:010/111/010"), Some(vec![(0, -1), (-1, 0), (0, 0), (1, 0), (0, 1)]));
+        assert_eq!(offsets("grid:00100"), Some(vec![(0, 0)]));
+    }
+
+    #[test]
+    fn grid_rejects_bad_shapes() {
+        // Empty element, even sides and malformed rows
+        assert!(offsets("grid:000/000/000").is_none());
+        assert!(offsets("grid:11/11").is_none());
+        assert!(offsets("grid:111/111").is_none());
+        assert!(offsets("grid:111/11/111").is_none());
+        assert!(offsets("grid:121/111/111").is_none());
+        assert!(offsets("grid:").is_none());
+    }
+}
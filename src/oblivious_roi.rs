@@ -0,0 +1,107 @@
+use rayon::prelude::*;
+use tfhe::integer::{ClientKey as RadixClientKey, RadixCiphertext, ServerKey as RadixServerKey};
+use tfhe::shortint::{Ciphertext, ClientKey, ServerKey};
+
+use crate::encrypt_image::EncryptedImage;
+use crate::mask::EncryptedMask;
+use crate::pixel_count::{radix_server_key, sum_bits, sum_values, EncryptedCount};
+
+/// Region of interest whose bounds the server never sees.
+/// Covers `x0 <= x < x1` and `y0 <= y < y1`.
+pub struct EncryptedRoi {
+    pub x0: RadixCiphertext,
+    pub y0: RadixCiphertext,
+    pub x1: RadixCiphertext,
+    pub y1: RadixCiphertext,
+}
+
+/// Client side: encrypt the rectangle `(x, y, w, h)`. `num_blocks` must hold
+/// twice the largest image dimension, the sum taken to find the centre.
+pub fn encrypt_roi(rect: (u32, u32, u32, u32), num_blocks: usize, client_key: &ClientKey) -> EncryptedRoi {
+    let radix_key = RadixClientKey::from(client_key.clone());
+    let (x, y, w, h) = rect;
+    let enc = |v: u32| radix_key.encrypt_radix(v as u64, num_blocks);
+    EncryptedRoi {
+        x0: enc(x),
+        y0: enc(y),
+        x1: enc(x + w),
+        y1: enc(y + h),
+    }
+}
+
+/// Encrypted bits telling, for every coordinate below `len`, whether it lies
+/// in `[lo, hi)`.
+fn span_bits(lo: &RadixCiphertext, hi: &RadixCiphertext, len: u32, radix_key: &RadixServerKey) -> Vec<Ciphertext> {
+    (0..len)
+        .into_par_iter()
+        .map(|v| {
+            let above = radix_key.scalar_le_parallelized(lo, v as u64);
+            let below = radix_key.scalar_gt_parallelized(hi, v as u64);
+            radix_key.boolean_bitand(&above, &below).into_raw_parts()
+        })
+        .collect()
+}
+
+/// Encrypted bits telling, for every coordinate below `len`, whether it
+/// equals `value`.
+fn point_bits(value: &RadixCiphertext, len: u32, radix_key: &RadixServerKey) -> Vec<Ciphertext> {
+    (0..len)
+        .into_par_iter()
+        .map(|v| radix_key.scalar_eq_parallelized(value, v as u64).into_raw_parts())
+        .collect()
+}
+
+/// Server side: mask of the pixels inside the encrypted region.
+/// Columns and rows are compared once with the bounds, then combined per
+/// pixel with a single AND.
+pub fn roi_mask(roi: &EncryptedRoi, width: u32, height: u32, server_key: &ServerKey) -> EncryptedMask {
+    let radix_key = radix_server_key(server_key);
+    let cols = span_bits(&roi.x0, &roi.x1, width, &radix_key);
+    let rows = span_bits(&roi.y0, &roi.y1, height, &radix_key);
+    let data = (0..width * height)
+        .into_par_iter()
+        .map(|idx| server_key.and(&cols[(idx % width) as usize], &rows[(idx / width) as usize]))
+        .collect();
+    EncryptedMask::new(width, height, data)
+}
+
+/// Server side: encrypted colour of the centre pixel of the encrypted region,
+/// the same pixel the plaintext path uses as reference.
+/// Every pixel is multiplied by an encrypted "is centre" bit and the products
+/// are summed, so the server reads all pixels alike.
+pub fn roi_center_color(enc_img: &EncryptedImage, roi: &EncryptedRoi, server_key: &ServerKey) -> [Ciphertext; 3] {
+    let radix_key = radix_server_key(server_key);
+    let center = |lo: &RadixCiphertext, hi: &RadixCiphertext| {
+        radix_key.scalar_div_parallelized(&radix_key.add_parallelized(lo, hi), 2u64)
+    };
+    let cols = point_bits(&center(&roi.x0, &roi.x1), enc_img.width, &radix_key);
+    let rows = point_bits(&center(&roi.y0, &roi.y1), enc_img.height, &radix_key);
+    let width = enc_img.width;
+    let selected: Vec<Ciphertext> = (0..width * enc_img.height)
+        .into_par_iter()
+        .map(|idx| server_key.and(&cols[(idx % width) as usize], &rows[(idx / width) as usize]))
+        .collect();
+
+    let bit_mul = server_key.generate_lookup_table_bivariate(|bit, v| bit * v);
+    let max_value = server_key.message_modulus.0 - 1;
+    std::array::from_fn(|c| {
+        let picked: Vec<Ciphertext> = selected
+            .par_iter()
+            .enumerate()
+            .map(|(idx, sel)| server_key.apply_lookup_table_bivariate(sel, &enc_img.data[idx * 3 + c], &bit_mul))
+            .collect();
+        // At most one term is non zero, so the value sits in the lowest block
+        sum_values(&picked, max_value, server_key).ct.blocks()[0].clone()
+    })
+}
+
+/// Server side: number of set mask pixels inside the encrypted region.
+pub fn count_in_roi(mask: &EncryptedMask, inside: &EncryptedMask, server_key: &ServerKey) -> EncryptedCount {
+    let bits: Vec<Ciphertext> = mask
+        .data
+        .par_iter()
+        .zip(&inside.data)
+        .map(|(a, b)| server_key.and(a, b))
+        .collect();
+    sum_bits(&bits, server_key)
+}
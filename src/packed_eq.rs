@@ -0,0 +1,61 @@
+use rayon::prelude::*;
+use tfhe::shortint::server_key::LookupTableOwned;
+use tfhe::shortint::{Ciphertext, ServerKey};
+
+use crate::encrypt_image::EncryptedImage;
+
+/// Whole-pixel equality evaluated with a single lookup table.
+/// With `bits` bits per channel the three channels are packed into one
+/// ciphertext as `r + g * 2^bits + b * 2^(2 * bits)` using only linear
+/// operations, so `3 * bits` must fit in the message space.
+pub struct PackedEq {
+    bits: u32,
+    is_zero: LookupTableOwned,
+}
+
+impl PackedEq {
+    pub fn new(bits: u32, server_key: &ServerKey) -> Self {
+        let m = server_key.message_modulus.0;
+        assert!(
+            1u64 << (3 * bits) <= m,
+            "{} bits per channel do not fit in a message modulus of {}",
+            bits,
+            m
+        );
+        PackedEq {
+            bits,
+            is_zero: server_key.generate_lookup_table(move |x| (x % m == 0) as u64),
+        }
+    }
+
+    /// Pack one pixel into a single ciphertext without bootstrapping.
+    pub fn pack(&self, px: &[Ciphertext], server_key: &ServerKey) -> Ciphertext {
+        let g = server_key.unchecked_scalar_mul(&px[1], 1 << self.bits);
+        let b = server_key.unchecked_scalar_mul(&px[2], 1 << (2 * self.bits));
+        server_key.unchecked_add(&server_key.unchecked_add(&px[0], &g), &b)
+    }
+
+    /// Match bit of a packed pixel against the packed reference: the
+    /// difference is zero modulo the message space only when all channels
+    /// are equal.
+    pub fn matches(&self, px: &[Ciphertext], ref_packed: &Ciphertext, server_key: &ServerKey) -> Ciphertext {
+        let diff = server_key.unchecked_sub(&self.pack(px, server_key), ref_packed);
+        server_key.apply_lookup_table(&diff, &self.is_zero)
+    }
+}
+
+/// Homomorphic whole-pixel equality using one bootstrap per pixel.
+pub fn match_packed_bits(
+    enc_img: &EncryptedImage,
+    ref_rgb: &[Ciphertext; 3],
+    bits: u32,
+    server_key: &ServerKey,
+) -> Vec<Ciphertext> {
+    let packed = PackedEq::new(bits, server_key);
+    let ref_packed = packed.pack(ref_rgb, server_key);
+    enc_img
+        .data
+        .par_chunks(3)
+        .map(|px| packed.matches(px, &ref_packed, server_key))
+        .collect()
+}
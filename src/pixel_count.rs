@@ -0,0 +1,65 @@
+use crate::mask::EncryptedMask;
+use tfhe::integer::{ClientKey as RadixClientKey, RadixCiphertext, ServerKey as RadixServerKey};
+use tfhe::shortint::{Ciphertext, ClientKey, ServerKey};
+
+/// Encrypted counter stored as a radix ciphertext.
+/// `num_blocks` shortint blocks are enough to hold every value up to the
+/// number of pixels the counter was sized for.
+#[derive(Clone)]
+pub struct EncryptedCount {
+    pub ct: RadixCiphertext,
+    pub num_blocks: usize,
+}
+
+/// Number of radix blocks needed to represent values up to `max`.
+pub fn counter_blocks(max: u64, server_key: &ServerKey) -> usize {
+    let bits_per_block = server_key.message_modulus.0.ilog2() as usize;
+    let bits = (u64::BITS - max.leading_zeros()) as usize;
+    bits.div_ceil(bits_per_block).max(1)
+}
+
+/// Build the radix server key from the shortint key used everywhere else.
+pub fn radix_server_key(server_key: &ServerKey) -> RadixServerKey {
+    RadixServerKey::new_radix_server_key_from_shortint(server_key.clone())
+}
+
+/// Widen a single-block value into a radix ciphertext of `num_blocks` blocks.
+pub fn widen(ct: &Ciphertext, num_blocks: usize, radix_key: &RadixServerKey) -> RadixCiphertext {
+    let ct = RadixCiphertext::from(vec![ct.clone()]);
+    radix_key.extend_radix_with_trivial_zero_blocks_msb(&ct, num_blocks - 1)
+}
+
+/// Sum small encrypted values, each at most `max_value`, into a radix counter
+/// wide enough for the total.
+pub fn sum_values(values: &[Ciphertext], max_value: u64, server_key: &ServerKey) -> EncryptedCount {
+    let radix_key = radix_server_key(server_key);
+    let num_blocks = counter_blocks(values.len() as u64 * max_value, server_key);
+
+    // Widen every value into a radix counter with trivial zero high blocks
+    let widened: Vec<RadixCiphertext> = values
+        .iter()
+        .map(|v| widen(v, num_blocks, &radix_key))
+        .collect();
+
+    let ct = radix_key
+        .sum_ciphertexts_parallelized(widened.iter())
+        .unwrap_or_else(|| radix_key.create_trivial_zero_radix(num_blocks));
+    EncryptedCount { ct, num_blocks }
+}
+
+/// Sum encrypted 0/1 bits into a radix counter wide enough for `bits.len()`.
+pub fn sum_bits(bits: &[Ciphertext], server_key: &ServerKey) -> EncryptedCount {
+    sum_values(bits, 1, server_key)
+}
+
+/// Sum the encrypted match bits of a mask into a counter wide enough for the
+/// image size. Only this single total is meant to be decrypted.
+pub fn count_matched_pixels(mask: &EncryptedMask, server_key: &ServerKey) -> EncryptedCount {
+    sum_bits(&mask.data, server_key)
+}
+
+/// Client side: decrypt an aggregate counter.
+pub fn decrypt_count(count: &EncryptedCount, client_key: &ClientKey) -> u64 {
+    let radix_key = RadixClientKey::from(client_key.clone());
+    radix_key.decrypt_radix(&count.ct)
+}
@@ -0,0 +1,252 @@
+use std::fmt;
+use std::fs;
+use std::io;
+
+use image::DynamicImage;
+use rand::Rng;
+use serde_json::{json, Map, Value};
+use tfhe::shortint::{ClientKey, ServerKey};
+
+use crate::pixel_count::{counter_blocks, decrypt_count, radix_server_key, EncryptedCount};
+
+/// Noise distribution used for differentially private releases.
+#[derive(Clone, Copy, Debug)]
+pub enum DpMechanism {
+    /// Continuous Laplace noise rounded to the nearest integer.
+    Laplace,
+    /// Two-sided geometric (discrete Laplace) noise.
+    Geometric,
+}
+
+impl DpMechanism {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "laplace" => Some(DpMechanism::Laplace),
+            "geometric" => Some(DpMechanism::Geometric),
+            _ => None,
+        }
+    }
+}
+
+/// Parameters of the differential privacy layer.
+#[derive(Clone, Copy, Debug)]
+pub struct DpConfig {
+    pub epsilon: f64,
+    pub mechanism: DpMechanism,
+}
+
+impl DpConfig {
+    /// Draw one integer noise sample calibrated to `sensitivity / epsilon`,
+    /// where `sensitivity` is how much a single object can change the count.
+    pub fn sample_noise(&self, sensitivity: f64) -> i64 {
+        let mut rng = rand::thread_rng();
+        let scale = sensitivity / self.epsilon;
+        match self.mechanism {
+            DpMechanism::Laplace => {
+                let u: f64 = rng.gen_range(-0.5, 0.5);
+                (-scale * u.signum() * (1.0 - 2.0 * u.abs()).ln()).round() as i64
+            }
+            DpMechanism::Geometric => {
+                // Difference of two geometric variables with ratio exp(-1/scale)
+                let alpha = (-1.0 / scale).exp();
+                let mut geometric = || {
+                    let u: f64 = rng.gen_range(f64::MIN_POSITIVE, 1.0);
+                    (u.ln() / alpha.ln()).floor() as i64
+                };
+                geometric() - geometric()
+            }
+        }
+    }
+}
+
+/// Error returned when a release would exceed the per-image budget.
+#[derive(Debug)]
+pub struct BudgetExceeded {
+    pub image_id: String,
+    pub spent: f64,
+    pub requested: f64,
+    pub budget: f64,
+}
+
+impl fmt::Display for BudgetExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "privacy budget exceeded for image {}: spent {} + requested {} > budget {}",
+            self.image_id, self.spent, self.requested, self.budget
+        )
+    }
+}
+
+/// Per-image privacy budget ledger persisted as JSON on disk.
+/// The file maps image identifiers to the total epsilon spent so far.
+#[derive(Debug)]
+pub struct PrivacyLedger {
+    path: String,
+    spent: Map<String, Value>,
+}
+
+impl PrivacyLedger {
+    /// Load the ledger from `path`, starting empty only if the file does not
+    /// exist. An unreadable or malformed ledger is an error, since treating
+    /// it as empty would refund every budget.
+    pub fn load(path: &str) -> io::Result<Self> {
+        let buf = match fs::read_to_string(path) {
+            Ok(buf) => buf,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return Ok(PrivacyLedger {
+                    path: path.to_string(),
+                    spent: Map::new(),
+                });
+            }
+            Err(e) => return Err(e),
+        };
+        let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path, msg));
+        let json: Value = serde_json::from_str(&buf).map_err(|e| invalid(e.to_string()))?;
+        let spent = json["spent"]
+            .as_object()
+            .cloned()
+            .ok_or_else(|| invalid("missing \"spent\" object".into()))?;
+        Ok(PrivacyLedger {
+            path: path.to_string(),
+            spent,
+        })
+    }
+
+    /// Total epsilon already spent on `image_id`.
+    pub fn spent(&self, image_id: &str) -> f64 {
+        self.spent.get(image_id).and_then(Value::as_f64).unwrap_or(0.0)
+    }
+
+    /// Record a release of `epsilon` against `image_id` and persist the ledger.
+    pub fn charge(&mut self, image_id: &str, epsilon: f64, budget: f64) -> Result<(), BudgetExceeded> {
+        let spent = self.spent(image_id);
+        if spent + epsilon > budget {
+            return Err(BudgetExceeded {
+                image_id: image_id.to_string(),
+                spent,
+                requested: epsilon,
+                budget,
+            });
+        }
+        self.spent.insert(image_id.to_string(), json!(spent + epsilon));
+        let buf = serde_json::to_string_pretty(&json!({ "spent": self.spent })).unwrap();
+        fs::write(&self.path, buf).expect("failed to write privacy ledger");
+        Ok(())
+    }
+}
+
+/// Stable identifier of an image used as ledger key: the 64-bit FNV-1a hash
+/// of its size and pixel bytes. The algorithm is fixed, unlike the standard
+/// library hasher, so budgets survive toolchain upgrades.
+pub fn image_id(img: &DynamicImage) -> String {
+    const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+    const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+    let size = [img.width().to_le_bytes(), img.height().to_le_bytes()].concat();
+    let hash = size
+        .iter()
+        .chain(img.as_bytes())
+        .fold(FNV_OFFSET, |h, &b| (h ^ u64::from(b)).wrapping_mul(FNV_PRIME));
+    format!("{:016x}", hash)
+}
+
+/// Add integer noise to an encrypted counter.
+/// The counter is first widened so that the noisy value, including a negative
+/// one, fits in the lower half of the radix modulus.
+pub fn add_noise(count: &EncryptedCount, noise: i64, server_key: &ServerKey) -> EncryptedCount {
+    let radix_key = radix_server_key(server_key);
+    let msg_mod = server_key.message_modulus.0;
+    let capacity = msg_mod.saturating_pow(count.num_blocks as u32);
+    let needed = capacity.saturating_add(noise.unsigned_abs()).saturating_mul(2);
+    let num_blocks = counter_blocks(needed, server_key).max(count.num_blocks);
+    let ct = radix_key.extend_radix_with_trivial_zero_blocks_msb(&count.ct, num_blocks - count.num_blocks);
+    let ct = if noise >= 0 {
+        radix_key.scalar_add_parallelized(&ct, noise as u64)
+    } else {
+        radix_key.scalar_sub_parallelized(&ct, noise.unsigned_abs())
+    };
+    EncryptedCount { ct, num_blocks }
+}
+
+/// Client side: decrypt a noisy counter, clamping negative values to zero.
+pub fn decrypt_noisy_count(count: &EncryptedCount, client_key: &ClientKey) -> u64 {
+    let msg_mod = client_key.parameters().message_modulus().0;
+    let modulus = msg_mod.saturating_pow(count.num_blocks as u32);
+    let raw = decrypt_count(count, client_key);
+    if raw >= modulus / 2 { 0 } else { raw }
+}
+
+/// Optional DP layer in front of every counter release for one image.
+pub struct DpRelease {
+    pub config: DpConfig,
+    pub budget: f64,
+    pub ledger: PrivacyLedger,
+    pub image_id: String,
+}
+
+impl DpRelease {
+    /// Charge the ledger, add noise calibrated to `sensitivity` under
+    /// encryption and decrypt only the noisy value.
+    pub fn release(
+        &mut self,
+        count: &EncryptedCount,
+        sensitivity: f64,
+        client_key: &ClientKey,
+        server_key: &ServerKey,
+    ) -> Result<u64, BudgetExceeded> {
+        self.ledger
+            .charge(&self.image_id, self.config.epsilon, self.budget)?;
+        let noisy = add_noise(count, self.config.sample_noise(sensitivity), server_key);
+        Ok(decrypt_noisy_count(&noisy, client_key))
+    }
+
+    /// Same as `release` for a count the client already holds in plaintext.
+    pub fn release_value(&mut self, value: u64, sensitivity: f64) -> Result<u64, BudgetExceeded> {
+        self.ledger
+            .charge(&self.image_id, self.config.epsilon, self.budget)?;
+        Ok((value as i64 + self.config.sample_noise(sensitivity)).max(0) as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ledger_enforces_budget_and_persists() {
+        let path = std::env::temp_dir().join(format!("privacy_ledger_test_{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        let _ = fs::remove_file(path);
+
+        let mut ledger = PrivacyLedger::load(path).unwrap();
+        assert_eq!(ledger.spent("img"), 0.0);
+        ledger.charge("img", 0.5, 1.0).unwrap();
+        ledger.charge("img", 0.5, 1.0).unwrap();
+        let err = ledger.charge("img", 0.1, 1.0).unwrap_err();
+        assert_eq!(err.spent, 1.0);
+        ledger.charge("other", 0.3, 1.0).unwrap();
+
+        let reloaded = PrivacyLedger::load(path).unwrap();
+        assert_eq!(reloaded.spent("img"), 1.0);
+        assert_eq!(reloaded.spent("other"), 0.3);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn corrupt_ledger_is_an_error() {
+        let path = std::env::temp_dir().join(format!("privacy_ledger_corrupt_{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        fs::write(path, "{\"spent\": ").unwrap();
+        assert_eq!(PrivacyLedger::load(path).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        fs::write(path, "[]").unwrap();
+        assert!(PrivacyLedger::load(path).is_err());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn image_id_is_fixed() {
+        // FNV-1a of the size and six zero bytes; ledgers depend on it
+        assert_eq!(image_id(&DynamicImage::new_rgb8(2, 1)), "7831337128062746");
+        assert_ne!(image_id(&DynamicImage::new_rgb8(1, 2)), "7831337128062746");
+    }
+}
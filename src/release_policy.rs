@@ -0,0 +1,95 @@
+use std::fmt;
+
+use tfhe::integer::{BooleanBlock, ClientKey as RadixClientKey};
+use tfhe::shortint::{ClientKey, ServerKey};
+
+use crate::pixel_count::{radix_server_key, EncryptedCount};
+
+/// Encrypted counter gated by a minimum-count policy.
+/// `above` encrypts `count >= min_count`; `count` holds the original value
+/// when the policy is met and an encrypted zero otherwise, so the exact small
+/// number never leaves the server.
+pub struct GatedCount {
+    pub above: BooleanBlock,
+    pub count: EncryptedCount,
+    pub min_count: u64,
+}
+
+/// Result of a release under the threshold policy.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Released {
+    Count(u64),
+    /// The count was smaller than the given minimum and was not released.
+    BelowThreshold(u64),
+}
+
+impl fmt::Display for Released {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Released::Count(n) => write!(f, "{}個", n),
+            Released::BelowThreshold(k) => write!(f, "{}個未満", k),
+        }
+    }
+}
+
+/// Server side: compare the encrypted count against `min_count` and blank
+/// the value homomorphically when it is too small.
+pub fn gate_count(count: &EncryptedCount, min_count: u64, server_key: &ServerKey) -> GatedCount {
+    let radix_key = radix_server_key(server_key);
+    let above = radix_key.scalar_ge_parallelized(&count.ct, min_count);
+    let zero = radix_key.create_trivial_zero_radix(count.num_blocks);
+    let ct = radix_key.if_then_else_parallelized(&above, &count.ct, &zero);
+    GatedCount {
+        above,
+        count: EncryptedCount {
+            ct,
+            num_blocks: count.num_blocks,
+        },
+        min_count,
+    }
+}
+
+/// Client side: decrypt the policy marker first and only decrypt the count
+/// through `decrypt` when the policy allows it.
+pub fn release_gated<F>(gated: &GatedCount, client_key: &ClientKey, decrypt: F) -> Released
+where
+    F: FnOnce(&EncryptedCount) -> u64,
+{
+    let radix_key = RadixClientKey::from(client_key.clone());
+    if radix_key.decrypt_bool(&gated.above) {
+        Released::Count(decrypt(&gated.count))
+    } else {
+        Released::BelowThreshold(gated.min_count)
+    }
+}
+
+/// Client side: apply the same policy to a count that is only known in
+/// plaintext, such as a component count taken after decrypting a mask.
+/// `release` (e.g. the DP layer) only sees values that meet the policy.
+pub fn release_value<F>(value: u64, min_count: Option<u64>, release: F) -> Released
+where
+    F: FnOnce(u64) -> u64,
+{
+    match min_count {
+        Some(k) if value < k => Released::BelowThreshold(k),
+        _ => Released::Count(release(value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn suppressed_count_is_not_printed() {
+        let released = release_value(3, Some(5), |_| panic!("suppressed count must not be released"));
+        assert_eq!(released, Released::BelowThreshold(5));
+        assert_eq!(released.to_string(), "5個未満");
+    }
+
+    #[test]
+    fn count_at_minimum_is_released() {
+        assert_eq!(release_value(5, Some(5), |v| v), Released::Count(5));
+        assert_eq!(release_value(2, None, |v| v + 1).to_string(), "3個");
+    }
+}
@@ -0,0 +1,185 @@
+use std::f64::consts::PI;
+use std::fmt;
+
+use imageproc::geometry::{approximate_polygon_dp, arc_length, convex_hull, min_area_rect};
+use imageproc::point::Point;
+
+/// Geometric features of a closed contour.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ShapeFeatures {
+    /// Vertices of the Douglas-Peucker approximation.
+    pub vertices: usize,
+    pub area: f64,
+    pub perimeter: f64,
+    /// `4 * pi * area / perimeter^2`, 1 for a circle.
+    pub circularity: f64,
+    /// Area divided by convex hull area, 1 for convex shapes.
+    pub convexity: f64,
+    /// Long side over short side of the minimum area rectangle.
+    pub aspect_ratio: f64,
+    /// Holes inside the outer border. A single contour cannot see them, so
+    /// `from_contour` leaves this at 0 for the caller to fill in.
+    pub holes: usize,
+}
+
+/// Polygon area by the shoelace formula.
+pub fn polygon_area(points: &[Point<i32>]) -> f64 {
+    let n = points.len();
+    let twice: i64 = (0..n)
+        .map(|i| {
+            let (a, b) = (points[i], points[(i + 1) % n]);
+            a.x as i64 * b.y as i64 - b.x as i64 * a.y as i64
+        })
+        .sum();
+    twice.abs() as f64 / 2.0
+}
+
+/// Polygon approximation of a closed contour, `epsilon_ratio` times its
+/// perimeter away from the original at most.
+pub fn approximate_polygon(points: &[Point<i32>], epsilon_ratio: f64) -> Vec<Point<i32>> {
+    let perimeter = arc_length(points, true);
+    if points.len() < 3 || perimeter == 0.0 {
+        return points.to_vec();
+    }
+    approximate_polygon_dp(points, epsilon_ratio * perimeter, true)
+}
+
+impl ShapeFeatures {
+    pub fn from_contour(points: &[Point<i32>]) -> Self {
+        if points.len() < 3 {
+            return ShapeFeatures {
+                vertices: points.len(),
+                ..Default::default()
+            };
+        }
+        let area = polygon_area(points);
+        let perimeter = arc_length(points, true);
+        let hull_area = polygon_area(&convex_hull(points));
+        let rect = min_area_rect(points);
+        let side = |a: Point<i32>, b: Point<i32>| (((b.x - a.x).pow(2) + (b.y - a.y).pow(2)) as f64).sqrt();
+        let (w, h) = (side(rect[0], rect[1]), side(rect[1], rect[2]));
+        ShapeFeatures {
+            vertices: approximate_polygon(points, 0.02).len(),
+            area,
+            perimeter,
+            circularity: if perimeter > 0.0 { 4.0 * PI * area / perimeter.powi(2) } else { 0.0 },
+            convexity: if hull_area > 0.0 { area / hull_area } else { 0.0 },
+            aspect_ratio: if w.min(h) > 0.0 { w.max(h) / w.min(h) } else { 0.0 },
+            holes: 0,
+        }
+    }
+}
+
+/// Named shape classes recognised by `classify`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ShapeClass {
+    Triangle,
+    Square,
+    Rectangle,
+    Pentagon,
+    Hexagon,
+    Circle,
+    Ellipse,
+    Star,
+    Other,
+}
+
+impl fmt::Display for ShapeClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ShapeClass::Triangle => "三角形",
+            ShapeClass::Square => "正方形",
+            ShapeClass::Rectangle => "長方形",
+            ShapeClass::Pentagon => "五角形",
+            ShapeClass::Hexagon => "六角形",
+            ShapeClass::Circle => "円",
+            ShapeClass::Ellipse => "楕円",
+            ShapeClass::Star => "星形",
+            ShapeClass::Other => "その他",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Classify a shape from its features.
+/// Strongly concave shapes with many vertices are stars; convex shapes are
+/// split by vertex count, and round ones by circularity and aspect ratio.
+pub fn classify(f: &ShapeFeatures) -> ShapeClass {
+    if f.area == 0.0 {
+        return ShapeClass::Other;
+    }
+    if f.convexity < 0.75 && f.vertices >= 8 {
+        return ShapeClass::Star;
+    }
+    if f.convexity < 0.9 {
+        return ShapeClass::Other;
+    }
+    match f.vertices {
+        3 => ShapeClass::Triangle,
+        4 if f.aspect_ratio < 1.15 => ShapeClass::Square,
+        4 => ShapeClass::Rectangle,
+        5 => ShapeClass::Pentagon,
+        6 => ShapeClass::Hexagon,
+        _ if f.circularity > 0.85 && f.aspect_ratio < 1.15 => ShapeClass::Circle,
+        _ if f.circularity > 0.6 => ShapeClass::Ellipse,
+        _ => ShapeClass::Other,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GrayImage, Luma};
+    use imageproc::contours::{find_contours, BorderType};
+    use imageproc::drawing::{draw_filled_circle_mut, draw_polygon_mut};
+
+    /// Features of the single object drawn by `draw`.
+    fn features(draw: impl Fn(&mut GrayImage)) -> ShapeFeatures {
+        let mut img = GrayImage::new(200, 200);
+        draw(&mut img);
+        let contours = find_contours::<i32>(&img);
+        let outer: Vec<_> = contours.iter().filter(|c| c.border_type == BorderType::Outer).collect();
+        assert_eq!(outer.len(), 1);
+        ShapeFeatures::from_contour(&outer[0].points)
+    }
+
+    fn polygon(points: &[(i32, i32)]) -> impl Fn(&mut GrayImage) + '_ {
+        move |img| {
+            let poly: Vec<Point<i32>> = points.iter().map(|&(x, y)| Point::new(x, y)).collect();
+            draw_polygon_mut(img, &poly, Luma([255u8]));
+        }
+    }
+
+    #[test]
+    fn triangle() {
+        let f = features(polygon(&[(20, 170), (100, 30), (180, 170)]));
+        assert_eq!(f.vertices, 3);
+        assert_eq!(classify(&f), ShapeClass::Triangle);
+    }
+
+    #[test]
+    fn square() {
+        let f = features(polygon(&[(40, 40), (160, 40), (160, 160), (40, 160)]));
+        assert_eq!(f.vertices, 4);
+        assert_eq!(classify(&f), ShapeClass::Square);
+    }
+
+    #[test]
+    fn rectangle() {
+        let f = features(polygon(&[(20, 60), (180, 60), (180, 140), (20, 140)]));
+        assert_eq!(f.vertices, 4);
+        assert_eq!(classify(&f), ShapeClass::Rectangle);
+    }
+
+    #[test]
+    fn circle() {
+        let f = features(|img| draw_filled_circle_mut(img, (100, 100), 70, Luma([255u8])));
+        assert!(f.vertices > 6, "{} vertices", f.vertices);
+        assert_eq!(classify(&f), ShapeClass::Circle);
+    }
+
+    #[test]
+    fn empty_shape_is_other() {
+        assert_eq!(classify(&ShapeFeatures::default()), ShapeClass::Other);
+    }
+}
@@ -0,0 +1,368 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+
+use image::DynamicImage;
+use imageproc::point::Point;
+use rayon::prelude::*;
+use serde_json::Value;
+use tfhe::shortint::{Ciphertext, ClientKey, ServerKey};
+
+use crate::count_shape::{binarize, objects, Preprocess};
+use crate::feature_match::{encrypt_features, encrypt_range, features_in_range, FeatureTolerance};
+use crate::pixel_count::{counter_blocks, sum_bits, EncryptedCount};
+use crate::shape_class::ShapeFeatures;
+
+/// Named reference polygon with its own tolerances.
+/// Coordinates are in pixels, so area and perimeter are compared at the
+/// scale the shape was drawn.
+#[derive(Clone, Debug)]
+pub struct LibraryShape {
+    pub name: String,
+    pub points: Vec<Point<i32>>,
+    /// Expected number of holes.
+    pub holes: usize,
+    pub tolerance: FeatureTolerance,
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Token of SVG path data.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum PathToken {
+    Command(char),
+    Number(f64),
+}
+
+/// Split path data into commands and numbers following the SVG grammar:
+/// a number is an optional sign, digits with at most one decimal point and
+/// an optional exponent, so `1.5.5` is two numbers and `1e1` is one.
+fn tokenize_path(d: &str) -> Result<Vec<PathToken>, String> {
+    let chars: Vec<char> = d.chars().collect();
+    let digits = |i: &mut usize| {
+        let start = *i;
+        while *i < chars.len() && chars[*i].is_ascii_digit() {
+            *i += 1;
+        }
+        *i > start
+    };
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let ch = chars[i];
+        if ch.is_whitespace() || ch == ',' {
+            i += 1;
+        } else if ch.is_ascii_alphabetic() {
+            tokens.push(PathToken::Command(ch));
+            i += 1;
+        } else if ch.is_ascii_digit() || matches!(ch, '+' | '-' | '.') {
+            let start = i;
+            if matches!(ch, '+' | '-') {
+                i += 1;
+            }
+            let mut has_digits = digits(&mut i);
+            if chars.get(i) == Some(&'.') {
+                i += 1;
+                has_digits |= digits(&mut i);
+            }
+            if !has_digits {
+                return Err(format!("bad number in path at {}", start));
+            }
+            if matches!(chars.get(i), Some('e' | 'E')) {
+                let mut j = i + 1;
+                if matches!(chars.get(j), Some('+' | '-')) {
+                    j += 1;
+                }
+                if digits(&mut j) {
+                    i = j;
+                }
+            }
+            let text: String = chars[start..i].iter().collect();
+            let value = text.parse().map_err(|_| format!("bad number in path: {}", text))?;
+            tokens.push(PathToken::Number(value));
+        } else {
+            return Err(format!("unexpected character in path: {}", ch));
+        }
+    }
+    Ok(tokens)
+}
+
+/// Vertices of an SVG path made of straight segments.
+/// Supports `M`, `L`, `H`, `V` and `Z` in absolute and relative form for a
+/// single subpath; curves and further subpaths are rejected.
+pub fn parse_svg_path(d: &str) -> Result<Vec<Point<i32>>, String> {
+    let tokens = tokenize_path(d)?;
+    let mut i = 0;
+    let number = |i: &mut usize| -> Result<f64, String> {
+        match tokens.get(*i) {
+            Some(PathToken::Number(v)) => {
+                *i += 1;
+                Ok(*v)
+            }
+            Some(PathToken::Command(c)) => Err(format!("expected a number before {}", c)),
+            None => Err("truncated path".into()),
+        }
+    };
+    let mut points: Vec<(f64, f64)> = Vec::new();
+    let (mut cur, mut cmd) = ((0.0, 0.0), 'M');
+    while i < tokens.len() {
+        if let PathToken::Command(c) = tokens[i] {
+            if c.eq_ignore_ascii_case(&'m') && !points.is_empty() {
+                return Err("multiple subpaths are not supported".into());
+            }
+            cmd = c;
+            i += 1;
+            if cmd.eq_ignore_ascii_case(&'z') {
+                continue;
+            }
+        }
+        let (ox, oy) = if cmd.is_ascii_lowercase() { cur } else { (0.0, 0.0) };
+        cur = match cmd.to_ascii_uppercase() {
+            'M' | 'L' => {
+                let x = number(&mut i)?;
+                (ox + x, oy + number(&mut i)?)
+            }
+            'H' => (ox + number(&mut i)?, cur.1),
+            'V' => (cur.0, oy + number(&mut i)?),
+            'Z' => return Err("number after Z in path".into()),
+            other => return Err(format!("unsupported path command: {}", other)),
+        };
+        points.push(cur);
+        // Coordinates following a move are implicit line segments
+        cmd = match cmd {
+            'M' => 'L',
+            'm' => 'l',
+            c => c,
+        };
+    }
+    if points.len() > 1 && points.first() == points.last() {
+        points.pop();
+    }
+    Ok(points
+        .into_iter()
+        .map(|(x, y)| Point::new(x.round() as i32, y.round() as i32))
+        .collect())
+}
+
+/// Value of attribute `name` inside one SVG tag, quoted with `"` or `'`.
+fn svg_attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let pattern = format!("{}=", name);
+    tag.match_indices(&pattern).find_map(|(pos, _)| {
+        // Skip matches inside a longer name such as `data-d`
+        if !tag[..pos].ends_with(char::is_whitespace) {
+            return None;
+        }
+        let rest = &tag[pos + pattern.len()..];
+        let quote = rest.chars().next().filter(|q| matches!(q, '"' | '\''))?;
+        rest[1..].split(quote).next()
+    })
+}
+
+/// One library entry from JSON. Either `points` (`[[x, y], ...]`) or an SVG
+/// `path` gives the outline; `holes` and `tolerance` are optional.
+fn parse_entry(entry: &Value) -> Result<LibraryShape, String> {
+    let name = entry["name"].as_str().ok_or("library entry without name")?.to_string();
+    let points = if let Some(points) = entry["points"].as_array() {
+        points
+            .iter()
+            .map(|p| match (p[0].as_f64(), p[1].as_f64()) {
+                (Some(x), Some(y)) => Ok(Point::new(x.round() as i32, y.round() as i32)),
+                _ => Err(format!("bad point in {}", name)),
+            })
+            .collect::<Result<_, _>>()?
+    } else if let Some(d) = entry["path"].as_str() {
+        parse_svg_path(d)?
+    } else {
+        return Err(format!("{} has neither points nor path", name));
+    };
+
+    let defaults = FeatureTolerance::default();
+    let tol = &entry["tolerance"];
+    Ok(LibraryShape {
+        name,
+        points,
+        holes: entry["holes"].as_u64().unwrap_or(0) as usize,
+        tolerance: FeatureTolerance {
+            area: tol["area"].as_f64().unwrap_or(defaults.area),
+            perimeter: tol["perimeter"].as_f64().unwrap_or(defaults.perimeter),
+            vertices: tol["vertices"].as_u64().unwrap_or(defaults.vertices),
+            circularity: tol["circularity"].as_f64().unwrap_or(defaults.circularity),
+            holes: tol["holes"].as_u64(),
+        },
+    })
+}
+
+/// Every `<path>` element of an SVG document, named by its `id`, with
+/// default tolerances. Longer element names such as `<pattern>` are skipped.
+fn parse_svg_library(buf: &str) -> io::Result<Vec<LibraryShape>> {
+    buf.match_indices("<path")
+        .map(|(pos, tag)| &buf[pos + tag.len()..])
+        .filter(|rest| rest.starts_with(|c: char| c.is_whitespace() || c == '/' || c == '>'))
+        .enumerate()
+        .map(|(i, tag)| {
+            let tag = &tag[..tag.find('>').unwrap_or(tag.len())];
+            let d = svg_attribute(tag, "d").ok_or_else(|| invalid(format!("path {} has no d", i)))?;
+            Ok(LibraryShape {
+                name: svg_attribute(tag, "id").map_or_else(|| format!("path{}", i), str::to_string),
+                points: parse_svg_path(d).map_err(invalid)?,
+                holes: 0,
+                tolerance: FeatureTolerance::default(),
+            })
+        })
+        .collect()
+}
+
+/// Load a shape library.
+/// `.svg` files contribute every `<path>` element, named by its `id`, with
+/// default tolerances. Anything else is read as a JSON array of entries.
+pub fn load_library(path: &str) -> io::Result<Vec<LibraryShape>> {
+    let buf = fs::read_to_string(path)?;
+    if path.to_lowercase().ends_with(".svg") {
+        return parse_svg_library(&buf);
+    }
+    let json: Value = serde_json::from_str(&buf).map_err(|e| invalid(e.to_string()))?;
+    json.as_array()
+        .ok_or_else(|| invalid("library must be a JSON array".into()))?
+        .iter()
+        .map(|entry| parse_entry(entry).map_err(invalid))
+        .collect()
+}
+
+/// Count the objects of the image matching each library entry, grouped by
+/// name. The client encrypts the features of every object once and an
+/// interval per entry; the server checks every object against every entry.
+/// An object matching several entries of the same name is counted once.
+pub fn count_library_encrypted(
+    img: &DynamicImage,
+    prep: &Preprocess,
+    library: &[LibraryShape],
+    client_key: &ClientKey,
+    server_key: &ServerKey,
+) -> BTreeMap<String, EncryptedCount> {
+    let binary = binarize(img, prep);
+    let objects = objects(&binary);
+    let num_blocks = counter_blocks(4 * binary.width() as u64 * binary.height() as u64, server_key);
+    let max_holes = objects.iter().map(|(_, holes)| *holes).max().unwrap_or(0) as u64;
+
+    let features: Vec<_> = objects
+        .iter()
+        .map(|(c, holes)| {
+            let f = ShapeFeatures {
+                holes: *holes,
+                ..ShapeFeatures::from_contour(&c.points)
+            };
+            encrypt_features(&f, num_blocks, client_key)
+        })
+        .collect();
+
+    let mut by_name: BTreeMap<String, Vec<Ciphertext>> = BTreeMap::new();
+    for entry in library {
+        let reference = ShapeFeatures {
+            holes: entry.holes,
+            ..ShapeFeatures::from_contour(&entry.points)
+        };
+        // Without a hole tolerance the interval spans every hole count present
+        let tol = FeatureTolerance {
+            holes: Some(entry.tolerance.holes.unwrap_or(max_holes + entry.holes as u64)),
+            ..entry.tolerance
+        };
+        let range = encrypt_range(&reference, &tol, num_blocks, client_key);
+        let bits: Vec<Ciphertext> = features
+            .par_iter()
+            .map(|f| features_in_range(f, &range, server_key))
+            .collect();
+        match by_name.get_mut(&entry.name) {
+            Some(acc) => {
+                for (a, b) in acc.iter_mut().zip(&bits) {
+                    *a = server_key.or(a, b);
+                }
+            }
+            None => {
+                by_name.insert(entry.name.clone(), bits);
+            }
+        }
+    }
+    by_name
+        .into_iter()
+        .map(|(name, bits)| (name, sum_bits(&bits, server_key)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn xy(points: &[Point<i32>]) -> Vec<(i32, i32)> {
+        points.iter().map(|p| (p.x, p.y)).collect()
+    }
+
+    #[test]
+    fn absolute_and_relative_commands() {
+        let square = parse_svg_path("M 0,0 L 10,0 L 10,10 L 0,10 Z").unwrap();
+        assert_eq!(xy(&square), vec![(0, 0), (10, 0), (10, 10), (0, 10)]);
+        let relative = parse_svg_path("m5 5 h10 v10 h-10 z").unwrap();
+        assert_eq!(xy(&relative), vec![(5, 5), (15, 5), (15, 15), (5, 15)]);
+        // Coordinates after a move are implicit line segments
+        assert_eq!(xy(&parse_svg_path("M0 0 4 0 4 4").unwrap()), vec![(0, 0), (4, 0), (4, 4)]);
+    }
+
+    #[test]
+    fn numbers_follow_the_svg_grammar() {
+        assert_eq!(xy(&parse_svg_path("M1e1 2E+1L-1.5.5").unwrap()), vec![(10, 20), (-2, 1)]);
+        assert_eq!(xy(&parse_svg_path("M10-20L.5-.5").unwrap()), vec![(10, -20), (1, -1)]);
+        assert_eq!(
+            tokenize_path("M1.5.5").unwrap(),
+            vec![PathToken::Command('M'), PathToken::Number(1.5), PathToken::Number(0.5)]
+        );
+    }
+
+    #[test]
+    fn rejects_unsupported_paths() {
+        assert!(parse_svg_path("M0 0 C1 1 2 2 3 3").is_err());
+        assert!(parse_svg_path("M0 0 L1 1 Z M5 5 L6 6 Z").is_err());
+        assert!(parse_svg_path("M0 0 L1").is_err());
+        assert!(parse_svg_path("M0 0 L1 -").is_err());
+    }
+
+    #[test]
+    fn attributes_with_either_quote() {
+        let tag = " id='star'\n\td=\"M0 0 L1 1\" data-d=\"x\"";
+        assert_eq!(svg_attribute(tag, "id"), Some("star"));
+        assert_eq!(svg_attribute(tag, "d"), Some("M0 0 L1 1"));
+        assert_eq!(svg_attribute(tag, "fill"), None);
+    }
+
+    #[test]
+    fn json_entries() {
+        let entry: Value = serde_json::from_str(
+            r#"{"name": "tri", "points": [[0, 0], [10, 0], [5, 8]], "holes": 1, "tolerance": {"area": 0.5}}"#,
+        )
+        .unwrap();
+        let shape = parse_entry(&entry).unwrap();
+        assert_eq!(shape.name, "tri");
+        assert_eq!(xy(&shape.points), vec![(0, 0), (10, 0), (5, 8)]);
+        assert_eq!(shape.holes, 1);
+        assert_eq!(shape.tolerance.area, 0.5);
+        assert_eq!(shape.tolerance.perimeter, FeatureTolerance::default().perimeter);
+
+        let entry: Value = serde_json::from_str(r#"{"name": "sq", "path": "M0 0 H4 V4 H0 Z"}"#).unwrap();
+        assert_eq!(parse_entry(&entry).unwrap().points.len(), 4);
+
+        for bad in [r#"{"points": [[0, 0]]}"#, r#"{"name": "x"}"#, r#"{"name": "x", "points": [[0]]}"#] {
+            assert!(parse_entry(&serde_json::from_str(bad).unwrap()).is_err());
+        }
+    }
+
+    #[test]
+    fn svg_library_skips_longer_element_names() {
+        let svg = r#"<svg><defs><pattern id="hatch" d="M0 0 L1 1"/></defs>
+            <path id="square" d="M0 0 L4 0 L4 4 L0 4 Z"/><path
+            d="M0 0 L2 0 L0 2 Z"></path></svg>"#;
+        let shapes = parse_svg_library(svg).unwrap();
+        let names: Vec<&str> = shapes.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["square", "path1"]);
+        assert_eq!(xy(&shapes[1].points), vec![(0, 0), (2, 0), (0, 2)]);
+    }
+}
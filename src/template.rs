@@ -0,0 +1,170 @@
+use rayon::prelude::*;
+use tfhe::integer::{BooleanBlock, RadixCiphertext, ServerKey as RadixServerKey};
+use tfhe::shortint::server_key::BivariateLookupTableOwned;
+use tfhe::shortint::{Ciphertext, ServerKey};
+
+use crate::encrypt_image::EncryptedImage;
+use crate::pixel_count::{counter_blocks, radix_server_key, sum_bits, widen, EncryptedCount};
+
+/// Dissimilarity between the template and an image window, and the largest
+/// total score that still counts as a hit.
+#[derive(Clone, Copy, Debug)]
+pub enum TemplateMetric {
+    /// Sum of absolute channel differences.
+    Sad(u64),
+    /// Sum of squared channel differences.
+    Ssd(u64),
+}
+
+impl TemplateMetric {
+    /// Parse `sad:<max score>` or `ssd:<max score>`.
+    pub fn parse(spec: &str) -> Option<Self> {
+        let (kind, arg) = spec.split_once(':')?;
+        let threshold = arg.parse().ok()?;
+        match kind {
+            "sad" => Some(TemplateMetric::Sad(threshold)),
+            "ssd" => Some(TemplateMetric::Ssd(threshold)),
+            _ => None,
+        }
+    }
+
+    fn threshold(&self) -> u64 {
+        match *self {
+            TemplateMetric::Sad(t) | TemplateMetric::Ssd(t) => t,
+        }
+    }
+}
+
+/// Lookup tables giving the cost of one channel pair. Squared differences
+/// need up to two message blocks, produced by separate tables.
+struct CostLuts {
+    abs_diff: BivariateLookupTableOwned,
+    sq_lo: BivariateLookupTableOwned,
+    sq_hi: BivariateLookupTableOwned,
+}
+
+impl CostLuts {
+    fn new(server_key: &ServerKey) -> Self {
+        let m = server_key.message_modulus.0;
+        CostLuts {
+            abs_diff: server_key.generate_lookup_table_bivariate(|a, b| a.abs_diff(b)),
+            sq_lo: server_key.generate_lookup_table_bivariate(move |a, b| a.abs_diff(b).pow(2) % m),
+            sq_hi: server_key.generate_lookup_table_bivariate(move |a, b| a.abs_diff(b).pow(2) / m),
+        }
+    }
+
+    fn cost(
+        &self,
+        metric: &TemplateMetric,
+        a: &Ciphertext,
+        b: &Ciphertext,
+        num_blocks: usize,
+        radix_key: &RadixServerKey,
+        server_key: &ServerKey,
+    ) -> RadixCiphertext {
+        match metric {
+            TemplateMetric::Sad(_) => widen(
+                &server_key.apply_lookup_table_bivariate(a, b, &self.abs_diff),
+                num_blocks,
+                radix_key,
+            ),
+            TemplateMetric::Ssd(_) => {
+                let lo = server_key.apply_lookup_table_bivariate(a, b, &self.sq_lo);
+                let hi = server_key.apply_lookup_table_bivariate(a, b, &self.sq_hi);
+                radix_key.extend_radix_with_trivial_zero_blocks_msb(
+                    &RadixCiphertext::from(vec![lo, hi]),
+                    num_blocks.saturating_sub(2),
+                )
+            }
+        }
+    }
+}
+
+/// Count the occurrences of an encrypted patch, typically a crop of the same
+/// image, in the encrypted image.
+/// Every window position gets an encrypted SAD or SSD score; positions whose
+/// score is at most the threshold are hits. Non-maximum suppression then keeps
+/// a hit only if no other hit within `nms_radius` positions scores lower
+/// (ties go to the earlier position in raster order), so adjacent hits on one
+/// occurrence count once. Only the returned counter is meant to be decrypted.
+pub fn count_template_matches(
+    enc_img: &EncryptedImage,
+    template: &EncryptedImage,
+    metric: &TemplateMetric,
+    nms_radius: u32,
+    server_key: &ServerKey,
+) -> EncryptedCount {
+    let radix_key = radix_server_key(server_key);
+    let luts = CostLuts::new(server_key);
+    let (tw, th) = (template.width, template.height);
+    if tw > enc_img.width || th > enc_img.height {
+        return sum_bits(&[], server_key);
+    }
+    let (gw, gh) = (enc_img.width - tw + 1, enc_img.height - th + 1);
+
+    // Scores never reach `max_score`, the value given to suppressed windows
+    let m = server_key.message_modulus.0;
+    let value_max = match metric {
+        TemplateMetric::Sad(_) => m - 1,
+        TemplateMetric::Ssd(_) => (m - 1).pow(2),
+    };
+    let max_score = template.data.len() as u64 * value_max + 1;
+    let num_blocks = counter_blocks(max_score, server_key);
+    let zero: RadixCiphertext = radix_key.create_trivial_zero_radix(num_blocks);
+    let none: RadixCiphertext = radix_key.create_trivial_radix(max_score, num_blocks);
+
+    let scores: Vec<RadixCiphertext> = (0..gw * gh)
+        .into_par_iter()
+        .map(|idx| {
+            let (px, py) = (idx % gw, idx / gw);
+            let mut terms = Vec::with_capacity(template.data.len());
+            for ty in 0..th {
+                for tx in 0..tw {
+                    let img_off = (((py + ty) * enc_img.width + px + tx) * 3) as usize;
+                    let tpl_off = ((ty * tw + tx) * 3) as usize;
+                    for c in 0..3 {
+                        let (a, b) = (&enc_img.data[img_off + c], &template.data[tpl_off + c]);
+                        terms.push(luts.cost(metric, a, b, num_blocks, &radix_key, server_key));
+                    }
+                }
+            }
+            radix_key
+                .sum_ciphertexts_parallelized(terms.iter())
+                .unwrap_or_else(|| zero.clone())
+        })
+        .collect();
+
+    let hits: Vec<BooleanBlock> = scores
+        .par_iter()
+        .map(|s| radix_key.scalar_le_parallelized(s, metric.threshold()))
+        .collect();
+    let candidates: Vec<RadixCiphertext> = scores
+        .par_iter()
+        .zip(&hits)
+        .map(|(s, hit)| radix_key.if_then_else_parallelized(hit, s, &none))
+        .collect();
+
+    let r = nms_radius as i32;
+    let kept: Vec<Ciphertext> = (0..gw * gh)
+        .into_par_iter()
+        .map(|idx| {
+            let (x, y) = ((idx % gw) as i32, (idx / gw) as i32);
+            let score = &scores[idx as usize];
+            let mut keep = hits[idx as usize].clone();
+            for ny in (y - r).max(0)..=(y + r).min(gh as i32 - 1) {
+                for nx in (x - r).max(0)..=(x + r).min(gw as i32 - 1) {
+                    let j = (ny as u32 * gw + nx as u32) as usize;
+                    let other = &candidates[j];
+                    let not_better = match j.cmp(&(idx as usize)) {
+                        std::cmp::Ordering::Less => radix_key.gt_parallelized(other, score),
+                        std::cmp::Ordering::Greater => radix_key.ge_parallelized(other, score),
+                        std::cmp::Ordering::Equal => continue,
+                    };
+                    keep = radix_key.boolean_bitand(&keep, &not_better);
+                }
+            }
+            keep.into_raw_parts()
+        })
+        .collect();
+    sum_bits(&kept, server_key)
+}
@@ -0,0 +1,102 @@
+use rayon::prelude::*;
+use tfhe::integer::RadixCiphertext;
+use tfhe::shortint::{Ciphertext, ServerKey};
+
+use crate::gray::EncryptedGrayImage;
+use crate::mask::EncryptedMask;
+use crate::pixel_count::{radix_server_key, sum_bits, EncryptedCount};
+
+/// Binarize an encrypted gray image against an encrypted threshold.
+/// A pixel is foreground when its value is greater than or equal to the
+/// threshold.
+pub fn threshold_fixed(gray: &EncryptedGrayImage, thr: &Ciphertext, server_key: &ServerKey) -> EncryptedMask {
+    let bits = gray.data.par_iter().map(|px| server_key.ge(px, thr)).collect();
+    EncryptedMask::new(gray.width, gray.height, bits)
+}
+
+/// Encrypted histogram with one counter per gray level of the message space.
+pub fn encrypted_histogram(gray: &EncryptedGrayImage, server_key: &ServerKey) -> Vec<EncryptedCount> {
+    let levels = server_key.message_modulus.0;
+    (0..levels)
+        .map(|level| {
+            let is_level = server_key.generate_lookup_table(move |x| (x == level) as u64);
+            let bits: Vec<Ciphertext> = gray
+                .data
+                .par_iter()
+                .map(|px| server_key.apply_lookup_table(px, &is_level))
+                .collect();
+            sum_bits(&bits, server_key)
+        })
+        .collect()
+}
+
+/// Otsu threshold computed entirely on the encrypted histogram.
+/// For every candidate `t` the between-class variance is evaluated as
+/// `(N * sum0 - sum * w0)^2 / (w0 * (N - w0))`, and the best `t` is kept with
+/// encrypted comparisons, so neither the histogram nor the threshold is
+/// revealed.
+pub fn otsu_threshold(hist: &[EncryptedCount], pixels: u64, server_key: &ServerKey) -> Ciphertext {
+    let radix_key = radix_server_key(server_key);
+    let levels = hist.len() as u64;
+
+    // Wide enough for the squared numerator, the largest intermediate value
+    let bound = (pixels as u128 * pixels as u128 * levels as u128).pow(2);
+    let bits_per_block = server_key.message_modulus.0.ilog2();
+    let num_blocks = (u128::BITS - bound.leading_zeros()).div_ceil(bits_per_block) as usize;
+
+    let h: Vec<RadixCiphertext> = hist
+        .iter()
+        .map(|c| radix_key.extend_radix_with_trivial_zero_blocks_msb(&c.ct, num_blocks - c.num_blocks))
+        .collect();
+    let weighted: Vec<RadixCiphertext> = h
+        .iter()
+        .enumerate()
+        .map(|(level, c)| radix_key.scalar_mul_parallelized(c, level as u64))
+        .collect();
+    let total_sum = radix_key
+        .sum_ciphertexts_parallelized(weighted.iter())
+        .unwrap_or_else(|| radix_key.create_trivial_zero_radix(num_blocks));
+
+    let zero: RadixCiphertext = radix_key.create_trivial_zero_radix(num_blocks);
+    let n: RadixCiphertext = radix_key.create_trivial_radix(pixels, num_blocks);
+    let mut w0 = zero.clone();
+    let mut sum0 = zero.clone();
+    let mut best_score = zero.clone();
+    let mut best_t = zero.clone();
+
+    for t in 1..levels {
+        // Class 0 holds the levels below t
+        w0 = radix_key.add_parallelized(&w0, &h[t as usize - 1]);
+        sum0 = radix_key.add_parallelized(&sum0, &weighted[t as usize - 1]);
+
+        let a = radix_key.scalar_mul_parallelized(&sum0, pixels);
+        let b = radix_key.mul_parallelized(&total_sum, &w0);
+        let diff = radix_key.sub_parallelized(
+            &radix_key.max_parallelized(&a, &b),
+            &radix_key.min_parallelized(&a, &b),
+        );
+        let num = radix_key.mul_parallelized(&diff, &diff);
+        let denom = radix_key.mul_parallelized(&w0, &radix_key.sub_parallelized(&n, &w0));
+
+        // An empty class gives no split; keep its score at zero
+        let empty = radix_key.scalar_eq_parallelized(&denom, 0u64);
+        let score = radix_key.div_parallelized(&num, &denom);
+        let score = radix_key.if_then_else_parallelized(&empty, &zero, &score);
+
+        let better = radix_key.gt_parallelized(&score, &best_score);
+        let candidate: RadixCiphertext = radix_key.create_trivial_radix(t, num_blocks);
+        best_score = radix_key.if_then_else_parallelized(&better, &score, &best_score);
+        best_t = radix_key.if_then_else_parallelized(&better, &candidate, &best_t);
+    }
+
+    // The threshold is below the message modulus, so it lives in block 0
+    best_t.blocks()[0].clone()
+}
+
+/// Binarize an encrypted gray image with an Otsu threshold computed from its
+/// encrypted histogram.
+pub fn threshold_otsu(gray: &EncryptedGrayImage, server_key: &ServerKey) -> EncryptedMask {
+    let hist = encrypted_histogram(gray, server_key);
+    let thr = otsu_threshold(&hist, gray.data.len() as u64, server_key);
+    threshold_fixed(gray, &thr, server_key)
+}